@@ -1,87 +1,398 @@
-use anyhow::Result;
+#[cfg(test)]
+use crate::testing::Lcg;
+use anyhow::{anyhow, Result};
 use grid::Grid;
-use petgraph::algo::all_simple_paths;
+use petgraph::algo::toposort;
+use petgraph::dot::{Config, Dot};
 use petgraph::graph::NodeIndex;
-use petgraph::visit::Bfs;
-use petgraph::{Directed, Graph};
-use std::collections::{HashMap, HashSet};
+use petgraph::visit::{Bfs, EdgeRef};
+use petgraph::{Directed, Graph, Incoming};
+use std::collections::BinaryHeap;
+use std::fmt::Write;
 use std::fs;
 
-fn read_input(inputfile: &str) -> Result<Grid<u32>> {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HikingRules {
+    // allowed change in height per step, inclusive
+    pub steps: (i32, i32),
+    pub diagonal: bool,
+    pub start: u32,
+    pub end: u32,
+    pub impassable: Vec<char>,
+}
+
+impl Default for HikingRules {
+    fn default() -> HikingRules {
+        HikingRules {
+            steps: (1, 1),
+            diagonal: false,
+            start: 0,
+            end: 9,
+            impassable: vec!['.'],
+        }
+    }
+}
+
+impl HikingRules {
+    // Parse `--steps MIN:MAX`, `--diagonal`, `--heads H`, `--summits H` and
+    // `--impassable CHARS`, passing over the other day 10 options.
+    pub fn from_options(options: &[String]) -> Result<HikingRules> {
+        let mut rules = HikingRules::default();
+        let mut options = options.iter().peekable();
+        while let Some(option) = options.next() {
+            let mut value = || {
+                options
+                    .next_if(|value| !value.starts_with("--"))
+                    .ok_or_else(|| anyhow!("{option} needs a value"))
+            };
+            let parse_err = |text: &str| anyhow!("{option} needs whole numbers, not {text:?}");
+            match option.as_str() {
+                "--steps" => {
+                    let steps = value()?;
+                    let (min, max) = steps
+                        .split_once(':')
+                        .ok_or_else(|| anyhow!("steps {steps} is not MIN:MAX"))?;
+                    let min: i32 = min.parse().map_err(|_| parse_err(min))?;
+                    let max: i32 = max.parse().map_err(|_| parse_err(max))?;
+                    // level steps would let trails go round in circles
+                    if min > max || (min <= 0 && max >= 0) {
+                        return Err(anyhow!("steps {steps} must all climb or all descend"));
+                    }
+                    rules.steps = (min, max);
+                }
+                "--diagonal" => rules.diagonal = true,
+                "--heads" => {
+                    let height = value()?;
+                    rules.start = height.parse().map_err(|_| parse_err(height))?;
+                }
+                "--summits" => {
+                    let height = value()?;
+                    rules.end = height.parse().map_err(|_| parse_err(height))?;
+                }
+                "--impassable" => rules.impassable = value()?.chars().collect(),
+                "--prune" => (),
+                "--dot" | "--graphml" => {
+                    value()?;
+                }
+                _ => return Err(anyhow!("unknown option {option}")),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+
+fn parse_grid(data: &str, rules: &HikingRules) -> Result<Grid<Option<u32>>> {
+    let cols = data.lines().next().map_or(0, |line| line.chars().count());
+    if cols == 0 {
+        return Err(anyhow!("empty map"));
+    }
+    let mut cells = vec![];
+    for (i, line) in data.lines().enumerate() {
+        let len = line.chars().count();
+        if len != cols {
+            return Err(anyhow!("row {i} has {len} cells but row 0 has {cols}"));
+        }
+        for (j, c) in line.chars().enumerate() {
+            if rules.impassable.contains(&c) {
+                cells.push(None);
+            } else {
+                let height = c
+                    .to_digit(10)
+                    .ok_or_else(|| anyhow!("unexpected {c:?} at row {i}, column {j}"))?;
+                cells.push(Some(height));
+            }
+        }
+    }
+    Ok(Grid::from_vec(cells, cols))
+}
+
+fn read_input(inputfile: &str, rules: &HikingRules) -> Result<Grid<Option<u32>>> {
     let data = fs::read_to_string(inputfile)?;
-    let chars: Vec<_> = data
-        .chars()
-        .filter(|&c| c != '\n')
-        .map(|c| c.to_digit(10).unwrap_or(100))
-        .collect();
-    let grid = Grid::from_vec(chars, data.lines().next().unwrap().len());
-    Ok(grid)
+    parse_grid(&data, rules)
 }
 
-fn _print_grid(grid: &Grid<u32>) {
+fn _print_grid(grid: &Grid<Option<u32>>) {
     // print!("{}[2J", 27 as char);
     for rowidx in 0..grid.rows() {
         let rowstr: String = grid
             .iter_row(rowidx)
             .map(|c| match c {
-                100 => ".".to_string(),
-                _ => c.to_string(),
+                None => ".".to_string(),
+                Some(height) => height.to_string(),
             })
             .collect();
         println!("{rowstr}");
     }
 }
 
-pub fn run(inputfile: &str) -> Result<(i32, i32)> {
-    let grid = read_input(inputfile)?;
-    _print_grid(&grid);
-    let mut trail_heads: Vec<_> = vec![];
-    let mut nodes: HashMap<(usize, usize), _> = HashMap::new();
-    let mut graph = Graph::<u32, (), Directed>::new();
-    for ((i, j), &val) in grid.indexed_iter() {
+struct Trails {
+    graph: Graph<Option<u32>, (), Directed>,
+    heads: Vec<NodeIndex>,
+    summits: Vec<NodeIndex>,
+}
+
+fn build_trails(grid: &Grid<Option<u32>>, rules: &HikingRules) -> Trails {
+    let mut heads: Vec<_> = vec![];
+    let mut summits: Vec<_> = vec![];
+    let mut graph = Graph::with_capacity(grid.size().0 * grid.size().1, 0);
+    // nodes are added in row order, so the node at (i, j) has index i * cols + j
+    let node_at = |i: usize, j: usize| NodeIndex::new(i * grid.cols() + j);
+    for &val in grid.iter() {
         let node = graph.add_node(val);
-        nodes.insert((i, j), node);
-        if val == 0 {
-            trail_heads.push(node);
+        if val == Some(rules.start) {
+            heads.push(node);
+        }
+        if val == Some(rules.end) {
+            summits.push(node);
         }
     }
-    for ((i1, j1), &val1) in grid.indexed_iter() {
-        let n = nodes[&(i1, j1)];
-        let i1 = i1 as i32;
-        let j1 = j1 as i32;
-        for (i2, j2) in [(i1 - 1, j1), (i1 + 1, j1), (i1, j1 - 1), (i1, j1 + 1)] {
-            let i2 = i2 as usize;
-            let j2 = j2 as usize;
-            if let Some(&val2) = grid.get(i2, j2) {
-                if val2 == val1 + 1 {
-                    let m = nodes[&(i2, j2)];
+    let directions = if rules.diagonal { 8 } else { 4 };
+    let (min, max) = rules.steps;
+    for ((i1, j1), val1) in grid.indexed_iter() {
+        let Some(val1) = *val1 else { continue };
+        let n = node_at(i1, j1);
+        for (di, dj) in &DIRECTIONS[..directions] {
+            let i2 = (i1 as i32 + di) as usize;
+            let j2 = (j1 as i32 + dj) as usize;
+            if let Some(&Some(val2)) = grid.get(i2, j2) {
+                let climb = val2 as i32 - val1 as i32;
+                if (min..=max).contains(&climb) {
+                    let m = node_at(i2, j2);
                     graph.add_edge(n, m, ());
                 }
             }
         }
     }
-    // println!("{:#?}", graph);
-    let mut scores: Vec<usize> = vec![0; trail_heads.len()];
-    let mut end_points: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
+    Trails {
+        graph,
+        heads,
+        summits,
+    }
+}
+
+fn topological_order(trails: &Trails) -> Result<Vec<NodeIndex>> {
+    toposort(&trails.graph, None)
+        .map_err(|cycle| anyhow!("trail graph has a cycle through {:?}", cycle.node_id()))
+}
+
+// Number of distinct paths from each trail head to any summit, counting
+// paths to the summits from each node in reverse topological order.
+fn ratings(trails: &Trails, order: &[NodeIndex]) -> Vec<usize> {
+    let graph = &trails.graph;
+    let mut paths = vec![0usize; graph.node_count()];
+    for &summit in &trails.summits {
+        paths[summit.index()] = 1;
+    }
+    for &node in order.iter().rev() {
+        let from_here: usize = graph.neighbors(node).map(|m| paths[m.index()]).sum();
+        paths[node.index()] += from_here;
+    }
+    trails
+        .heads
+        .iter()
+        .map(|head| paths[head.index()])
+        .collect()
+}
 
-    for (idx, trail_head) in trail_heads.iter().enumerate() {
-        let mut bfs = Bfs::new(&graph, *trail_head);
-        while let Some(node) = bfs.next(&graph) {
-            if graph[node] == 9 {
-                scores[idx] += 1;
-                end_points.entry(*trail_head).or_default().insert(node);
+// Number of summits reachable from each trail head. Summits are taken 64
+// at a time as bits of a u64; each batch is propagated back only through
+// the nodes that can reach one of its summits, visiting them in reverse
+// topological order so every successor is done before its predecessors.
+fn scores(trails: &Trails, order: &[NodeIndex]) -> Vec<usize> {
+    let graph = &trails.graph;
+    let mut rank = vec![0; graph.node_count()];
+    for (idx, node) in order.iter().enumerate() {
+        rank[node.index()] = idx;
+    }
+    let mut bits = vec![0u64; graph.node_count()];
+    let mut seen = vec![false; graph.node_count()];
+    let mut counts = vec![0; graph.node_count()];
+    for batch in trails.summits.chunks(64) {
+        let mut queue = BinaryHeap::new();
+        let mut touched = vec![];
+        for (bit, summit) in batch.iter().enumerate() {
+            bits[summit.index()] |= 1 << bit;
+            seen[summit.index()] = true;
+            touched.push(*summit);
+        }
+        for summit in batch {
+            for m in graph.neighbors_directed(*summit, Incoming) {
+                queue.push((rank[m.index()], m));
             }
         }
+        while let Some((_, node)) = queue.pop() {
+            if seen[node.index()] {
+                continue;
+            }
+            seen[node.index()] = true;
+            touched.push(node);
+            bits[node.index()] |= graph
+                .neighbors(node)
+                .fold(0, |acc, m| acc | bits[m.index()]);
+            for m in graph.neighbors_directed(node, Incoming) {
+                if !seen[m.index()] {
+                    queue.push((rank[m.index()], m));
+                }
+            }
+        }
+        for node in touched {
+            counts[node.index()] += bits[node.index()].count_ones() as usize;
+            bits[node.index()] = 0;
+            seen[node.index()] = false;
+        }
     }
+    trails
+        .heads
+        .iter()
+        .map(|head| counts[head.index()])
+        .collect()
+}
 
-    let mut ratings: Vec<usize> = vec![0; trail_heads.len()];
-    for (idx, trail_head) in trail_heads.iter().enumerate() {
-        for end_point in &end_points[&trail_head] {
-            ratings[idx] += all_simple_paths::<Vec<_>, _>(&graph, *trail_head, *end_point, 0, None)
-                .collect::<Vec<_>>()
-                .len();
+// Score and rating of each trail head.
+fn survey(grid: &Grid<Option<u32>>, rules: &HikingRules) -> Result<(Vec<usize>, Vec<usize>)> {
+    let trails = build_trails(grid, rules);
+    let order = topological_order(&trails)?;
+    Ok((scores(&trails, &order), ratings(&trails, &order)))
+}
+
+pub fn hike(inputfile: &str, rules: &HikingRules) -> Result<(usize, usize)> {
+    let grid = read_input(inputfile, rules)?;
+    let (scores, ratings) = survey(&grid, rules)?;
+    let score = scores.iter().sum();
+    let rating = ratings.iter().sum();
+    println!(
+        "{} trail heads with {rules:?}: score {score}, rating {rating}",
+        scores.len()
+    );
+    Ok((score, rating))
+}
+
+// Height and (row, column) of each cell in an exported trail graph.
+type Cell = (u32, (usize, usize));
+
+// The trail graph without impassable cells, and if `prune` is set without
+// any cells that can't be reached from a trail head.
+fn trail_graph(grid: &Grid<Option<u32>>, rules: &HikingRules, prune: bool) -> Graph<Cell, ()> {
+    let trails = build_trails(grid, rules);
+    let graph = &trails.graph;
+    let mut keep = vec![!prune; graph.node_count()];
+    if prune {
+        for &head in &trails.heads {
+            let mut bfs = Bfs::new(graph, head);
+            while let Some(node) = bfs.next(graph) {
+                keep[node.index()] = true;
+            }
         }
     }
+    graph.filter_map(
+        |node, &height| {
+            let pos = (node.index() / grid.cols(), node.index() % grid.cols());
+            Some((height?, pos)).filter(|_| keep[node.index()])
+        },
+        |_, &edge| Some(edge),
+    )
+}
+
+// Graphviz DOT with each cell pinned at its grid position (for `neato -n`
+// or `fdp`), trail heads as boxes and summits as double circles.
+fn to_dot(graph: &Graph<Cell, ()>, rules: &HikingRules) -> String {
+    let node_attrs = |_, (_, &(height, (row, col))): (NodeIndex, &Cell)| {
+        let shape = if height == rules.start {
+            "box"
+        } else if height == rules.end {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        // y grows upwards in graphviz
+        format!(
+            "label=\"{height}\" shape={shape} pos=\"{},{}!\"",
+            col * 72,
+            -(row as i64) * 72
+        )
+    };
+    let dot = Dot::with_attr_getters(
+        graph,
+        &[Config::EdgeNoLabel, Config::NodeNoLabel],
+        &|_, _| String::new(),
+        &node_attrs,
+    );
+    format!("{dot:?}")
+}
+
+fn to_graphml(graph: &Graph<Cell, ()>) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    for key in ["height", "row", "col"] {
+        writeln!(
+            xml,
+            "  <key id=\"{key}\" for=\"node\" attr.name=\"{key}\" attr.type=\"int\"/>"
+        )
+        .unwrap();
+    }
+    xml.push_str("  <graph id=\"trails\" edgedefault=\"directed\">\n");
+    for node in graph.node_indices() {
+        let (height, (row, col)) = graph[node];
+        writeln!(xml, "    <node id=\"n{}\">", node.index()).unwrap();
+        writeln!(xml, "      <data key=\"height\">{height}</data>").unwrap();
+        writeln!(xml, "      <data key=\"row\">{row}</data>").unwrap();
+        writeln!(xml, "      <data key=\"col\">{col}</data>").unwrap();
+        xml.push_str("    </node>\n");
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            xml,
+            "    <edge source=\"n{}\" target=\"n{}\"/>",
+            edge.source().index(),
+            edge.target().index()
+        )
+        .unwrap();
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+// Write the trail graph to Graphviz DOT and/or GraphML files.
+pub fn export(
+    inputfile: &str,
+    rules: &HikingRules,
+    prune: bool,
+    dotfile: Option<&str>,
+    graphmlfile: Option<&str>,
+) -> Result<()> {
+    let grid = read_input(inputfile, rules)?;
+    let graph = trail_graph(&grid, rules, prune);
+    println!(
+        "Trail graph: {} nodes, {} edges",
+        graph.node_count(),
+        graph.edge_count()
+    );
+    if let Some(dotfile) = dotfile {
+        fs::write(dotfile, to_dot(&graph, rules))?;
+    }
+    if let Some(graphmlfile) = graphmlfile {
+        fs::write(graphmlfile, to_graphml(&graph))?;
+    }
+    Ok(())
+}
+
+pub fn run(inputfile: &str) -> Result<(i32, i32)> {
+    let rules = HikingRules::default();
+    let grid = read_input(inputfile, &rules)?;
+    _print_grid(&grid);
+    let (scores, ratings) = survey(&grid, &rules)?;
 
     println!("scores {:?}", scores);
     println!("ratings {:?}", ratings);
@@ -100,3 +411,146 @@ fn test_1() {
 fn test_2() {
     assert_eq!(run("./input/day10_small.txt").unwrap(), (2, 227));
 }
+
+#[cfg(test)]
+fn generate(rows: usize, cols: usize) -> String {
+    let mut lcg = Lcg::new();
+    let mut data = String::new();
+    for i in 0..rows {
+        for j in 0..cols {
+            let state = lcg.next_u64();
+            // diagonal bands of rising height, with the odd bump and gap
+            let bump = usize::from(state >> 61 == 0);
+            let height = (i + j + bump) % 10;
+            data.push(if state >> 58 == 1 {
+                '.'
+            } else {
+                char::from(b'0' + height as u8)
+            });
+        }
+        data.push('\n');
+    }
+    data
+}
+
+// Walk every path from a node, counting the paths and collecting summits.
+#[cfg(test)]
+fn walk(trails: &Trails, node: NodeIndex, summits: &mut Vec<NodeIndex>) -> usize {
+    let mut paths = 0;
+    if trails.summits.contains(&node) {
+        summits.push(node);
+        paths += 1;
+    }
+    let graph = &trails.graph;
+    paths
+        + graph
+            .neighbors(node)
+            .map(|m| walk(trails, m, summits))
+            .sum::<usize>()
+}
+
+#[test]
+fn test_walk() {
+    let data = generate(40, 70);
+    let default = HikingRules::default();
+    let diagonal = HikingRules {
+        diagonal: true,
+        ..HikingRules::default()
+    };
+    let descending = HikingRules {
+        steps: (-3, -1),
+        start: 9,
+        end: 0,
+        ..HikingRules::default()
+    };
+    for rules in [default, diagonal, descending] {
+        let grid = parse_grid(&data, &rules).unwrap();
+        let trails = build_trails(&grid, &rules);
+        let order = topological_order(&trails).unwrap();
+        let scores = scores(&trails, &order);
+        let ratings = ratings(&trails, &order);
+        assert!(trails.summits.len() > 64);
+        assert!(ratings.iter().any(|&rating| rating > 1));
+        for (idx, &head) in trails.heads.iter().enumerate() {
+            let mut summits = vec![];
+            assert_eq!(ratings[idx], walk(&trails, head, &mut summits));
+            summits.sort();
+            summits.dedup();
+            assert_eq!(scores[idx], summits.len());
+        }
+    }
+}
+
+#[test]
+fn test_rules() {
+    let options = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let rules = HikingRules::from_options(&options("--steps -1:-1 --heads 9 --summits 0")).unwrap();
+    assert_eq!(rules.steps, (-1, -1));
+    // the same trails walked downhill
+    assert_eq!(hike("./input/day10_test.txt", &rules).unwrap().1, 81);
+    assert!(HikingRules::from_options(&options("--steps -1:1")).is_err());
+    assert!(HikingRules::from_options(&options("--steps 2:1")).is_err());
+    let err = |s: &str| {
+        HikingRules::from_options(&options(s))
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(err("--steps 1:x"), "--steps needs whole numbers, not \"x\"");
+    assert_eq!(err("--heads --diagonal"), "--heads needs a value");
+    assert_eq!(err("--diagnal"), "unknown option --diagnal");
+    assert!(HikingRules::from_options(&options("--prune --dot trails.dot")).is_ok());
+    let rules = HikingRules::from_options(&options("--impassable #")).unwrap();
+    let grid = parse_grid("01#\n#23\n", &rules).unwrap();
+    assert_eq!(grid[(1, 0)], None);
+    assert_eq!(grid[(1, 2)], Some(3));
+    assert!(parse_grid("01.\n", &rules).is_err());
+    let err = parse_grid("0123\n45\n67\n", &rules).unwrap_err();
+    assert_eq!(err.to_string(), "row 1 has 2 cells but row 0 has 4");
+    let rules = HikingRules {
+        steps: (1, 2),
+        diagonal: true,
+        end: 3,
+        ..rules
+    };
+    let (scores, ratings) = survey(&grid, &rules).unwrap();
+    // 0-1-2-3, 0-1-3 and 0-2-3
+    assert_eq!((scores, ratings), (vec![1], vec![3]));
+}
+
+#[test]
+fn test_export() {
+    let rules = HikingRules::default();
+    let grid = parse_grid("0123\n1.34\n9765\n", &rules).unwrap();
+    let graph = trail_graph(&grid, &rules, false);
+    assert_eq!((graph.node_count(), graph.edge_count()), (11, 10));
+    // the 9 can't be reached from the head
+    let graph = trail_graph(&grid, &rules, true);
+    assert_eq!((graph.node_count(), graph.edge_count()), (10, 10));
+    let dot = to_dot(&graph, &rules);
+    assert!(dot.starts_with("digraph {"));
+    assert!(dot.contains("label=\"0\" shape=box pos=\"0,0!\""));
+    assert!(dot.contains("label=\"4\" shape=circle pos=\"216,-72!\""));
+    assert!(dot.contains(" -> "));
+    let graphml = to_graphml(&graph);
+    assert_eq!(graphml.matches("<node ").count(), 10);
+    assert_eq!(graphml.matches("<edge ").count(), 10);
+    assert!(graphml.contains("<data key=\"row\">2</data>"));
+}
+
+// Time scores and ratings on a generated 2000x2000 map.
+#[test]
+#[ignore]
+fn bench_trails() {
+    use std::time::Instant;
+
+    let rules = HikingRules::default();
+    let grid = parse_grid(&generate(2000, 2000), &rules).unwrap();
+    let start = Instant::now();
+    let (scores, ratings) = survey(&grid, &rules).unwrap();
+    let score: usize = scores.iter().sum();
+    let rating: usize = ratings.iter().sum();
+    println!(
+        "2000x2000: score {score}, rating {rating} in {:?}",
+        start.elapsed()
+    );
+}
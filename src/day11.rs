@@ -1,5 +1,8 @@
-use anyhow::Result;
-use std::{collections::HashMap, fs};
+use anyhow::{anyhow, Result};
+#[cfg(feature = "bigint")]
+use num_bigint::BigUint;
+use std::time::Instant;
+use std::{collections::HashMap, fs, mem};
 
 fn read_input(inputfile: &str) -> Result<Vec<usize>> {
     let data = fs::read_to_string(inputfile)?;
@@ -22,19 +25,11 @@ fn part1(inputfile: &str, n_blink: usize) -> Result<usize> {
 
 fn part2(inputfile: &str, n_blink: usize) -> Result<usize> {
     let stones = read_input(inputfile)?;
-    let mut cache: HashMap<usize, usize> = HashMap::new();
-    for stone in stones {
-        cache.insert(stone, 1);
+    let mut stones = Stones::new(stones.iter().map(|&s| s as u64));
+    for _ in 0..n_blink {
+        stones.blink()?;
     }
-    for i in 0..n_blink {
-        blink2(&mut cache);
-        // println!(
-        //     "{:?}",
-        //     cache.iter().filter(|(&_k, &v)| v != 0).collect::<Vec<_>>()
-        // );
-        println!("{} {}", i, cache.values().sum::<usize>());
-    }
-    Ok(cache.values().sum::<usize>())
+    usize::try_from(stones.total()?).map_err(|_| anyhow!("too many stones to count in a usize"))
 }
 
 fn blink(stones: Vec<usize>) -> Vec<usize> {
@@ -62,34 +57,153 @@ fn blink(stones: Vec<usize>) -> Vec<usize> {
         .collect()
 }
 
-fn blink2(stones: &mut HashMap<usize, usize>) {
-    for (&s, &count) in stones.clone().iter() {
-        if count == 0 {
-            continue;
+// Stones are engraved with a u64 until multiplying by 2024 overflows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Stone {
+    Small(u64),
+    Big(u128),
+}
+
+impl Stone {
+    fn new(value: u128) -> Stone {
+        match u64::try_from(value) {
+            Ok(value) => Stone::Small(value),
+            Err(_) => Stone::Big(value),
+        }
+    }
+
+    // The stone or pair of stones this one becomes after a blink.
+    fn blink(self) -> Result<(Stone, Option<Stone>)> {
+        match self {
+            Stone::Small(0) => Ok((Stone::Small(1), None)),
+            Stone::Small(s) => match split_u64(s) {
+                Some((l, r)) => Ok((Stone::Small(l), Some(Stone::Small(r)))),
+                None => Ok((
+                    s.checked_mul(2024)
+                        .map_or(Stone::Big(s as u128 * 2024), Stone::Small),
+                    None,
+                )),
+            },
+            Stone::Big(s) => match split_u128(s) {
+                Some((l, r)) => Ok((Stone::new(l), Some(Stone::new(r)))),
+                None => {
+                    let s = s
+                        .checked_mul(2024)
+                        .ok_or_else(|| anyhow!("stone {s} overflows 128 bits"))?;
+                    Ok((Stone::Big(s), None))
+                }
+            },
+        }
+    }
+}
+
+// Halves of the digits of a number with an even number of digits.
+fn split_u64(s: u64) -> Option<(u64, u64)> {
+    let digits = s.checked_ilog10()? + 1;
+    if digits % 2 != 0 {
+        return None;
+    }
+    let half = 10u64.pow(digits / 2);
+    Some((s / half, s % half))
+}
+
+fn split_u128(s: u128) -> Option<(u128, u128)> {
+    let digits = s.checked_ilog10()? + 1;
+    if digits % 2 != 0 {
+        return None;
+    }
+    let half = 10u128.pow(digits / 2);
+    Some((s / half, s % half))
+}
+
+// Counts of stones overflow 128 bits after a couple of hundred blinks, so
+// use arbitrary precision where it's available.
+#[cfg(not(feature = "bigint"))]
+type Count = u128;
+#[cfg(feature = "bigint")]
+type Count = BigUint;
+
+#[cfg(not(feature = "bigint"))]
+fn add_count(total: &mut Count, count: &Count) -> Result<()> {
+    *total = total
+        .checked_add(*count)
+        .ok_or_else(|| anyhow!("stone count overflows 128 bits, build with --features bigint"))?;
+    Ok(())
+}
+
+#[cfg(feature = "bigint")]
+fn add_count(total: &mut Count, count: &Count) -> Result<()> {
+    *total += count;
+    Ok(())
+}
+
+// Number of stones with each engraving. A blink fills the spare map from
+// the current one and swaps them, so no counts are cloned and stones that
+// have all changed into something else are gone rather than left at zero.
+struct Stones {
+    counts: HashMap<Stone, Count>,
+    spare: HashMap<Stone, Count>,
+}
+
+impl Stones {
+    fn new(stones: impl IntoIterator<Item = u64>) -> Stones {
+        let mut counts: HashMap<Stone, Count> = HashMap::new();
+        for stone in stones {
+            *counts
+                .entry(Stone::Small(stone))
+                .or_insert(Count::from(0u8)) += Count::from(1u8);
+        }
+        Stones {
+            counts,
+            spare: HashMap::new(),
+        }
+    }
+
+    fn blink(&mut self) -> Result<()> {
+        self.spare.clear();
+        for (stone, count) in &self.counts {
+            let (first, second) = stone.blink()?;
+            for stone in [Some(first), second].into_iter().flatten() {
+                let total = self.spare.entry(stone).or_insert(Count::from(0u8));
+                add_count(total, count)?;
+            }
         }
-        let s_str = s.to_string();
-        if s == 0 {
-            stones.entry(1).and_modify(|e| *e += count).or_insert(count);
-        } else if s_str.len() % 2 == 0 {
-            let l: usize = s_str[..s_str.len() / 2].parse().unwrap();
-            let r: usize = s_str[s_str.len() / 2..].parse().unwrap();
-            // println!(
-            //     "s={} l={} r={} count={} half_count={}",
-            //     s, l, r, count, half_count
-            // );
-            stones.entry(l).and_modify(|e| *e += count).or_insert(count);
-            stones.entry(r).and_modify(|e| *e += count).or_insert(count);
-        } else {
-            stones
-                .entry(s * 2024)
-                .and_modify(|e| *e += count)
-                .or_insert(count);
+        mem::swap(&mut self.counts, &mut self.spare);
+        Ok(())
+    }
+
+    fn total(&self) -> Result<Count> {
+        let mut total = Count::from(0u8);
+        for count in self.counts.values() {
+            add_count(&mut total, count)?;
         }
-        // println!("stones={:?}, s={}, count={}", stones, s, count);
-        stones.entry(s).and_modify(|e| *e -= count);
+        Ok(total)
     }
 }
 
+// Count the stones after `n_blink` blinks, with the number of distinct
+// engravings and how many of those needed more than 64 bits.
+pub fn blinks(inputfile: &str, n_blink: usize) -> Result<()> {
+    let start = Instant::now();
+    let stones = read_input(inputfile)?;
+    let mut stones = Stones::new(stones.iter().map(|&s| s as u64));
+    for _ in 0..n_blink {
+        stones.blink()?;
+    }
+    let big = stones
+        .counts
+        .keys()
+        .filter(|stone| matches!(stone, Stone::Big(_)))
+        .count();
+    println!(
+        "{} stones after {n_blink} blinks, {} distinct ({big} beyond 64 bits), in {:?}",
+        stones.total()?,
+        stones.counts.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
 #[test]
 fn test_blink_once() {
     let stones: Vec<usize> = vec![0, 1, 10, 99, 999];
@@ -116,33 +230,58 @@ fn test_blink_multiple() {
 
 #[test]
 fn test_blink2() {
-    let stones: Vec<usize> = vec![0, 1, 10, 99, 999];
-    let mut cache: HashMap<usize, usize> = HashMap::new();
-    for stone in stones {
-        cache.insert(stone, 1);
-    }
-    blink2(&mut cache);
-    println!("{:?}", cache);
-    println!("{}", cache.values().sum::<usize>());
-    assert_eq!(cache.values().sum::<usize>(), 7);
+    let mut stones = Stones::new([0, 1, 10, 99, 999]);
+    stones.blink().unwrap();
+    assert_eq!(stones.total().unwrap(), Count::from(7u8));
+    // 1, 0 and 9 twice
+    assert_eq!(stones.counts[&Stone::Small(1)], Count::from(2u8));
+    assert_eq!(stones.counts[&Stone::Small(9)], Count::from(2u8));
+    assert!(!stones.counts.contains_key(&Stone::Small(10)));
 }
 
 #[test]
 fn test_blink2_multiple() {
-    let stones: Vec<usize> = vec![125, 17];
-    let mut cache: HashMap<usize, usize> = HashMap::new();
-    for stone in stones {
-        cache.insert(stone, 1);
-    }
+    let mut stones = Stones::new([125, 17]);
     for _ in 0..6 {
-        blink2(&mut cache);
-        println!(
-            "{:?}",
-            cache.iter().filter(|(&_k, &v)| v != 0).collect::<Vec<_>>()
-        );
+        stones.blink().unwrap();
+    }
+    assert_eq!(stones.total().unwrap(), Count::from(22u8));
+    assert_eq!(stones.counts[&Stone::Small(2)], Count::from(4u8));
+    assert!(stones
+        .counts
+        .values()
+        .all(|count| *count > Count::from(0u8)));
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(split_u64(0), None);
+    assert_eq!(split_u64(999), None);
+    assert_eq!(split_u64(1000), Some((10, 0)));
+    assert_eq!(split_u64(u64::MAX), Some((1844674407, 3709551615)));
+    assert_eq!(split_u128(10u128.pow(20)), None);
+    assert_eq!(split_u128(10u128.pow(23)), Some((100_000_000_000, 0)));
+}
+
+#[test]
+fn test_big_stones() {
+    let s = 10u64.pow(18);
+    let (stone, none) = Stone::Small(s).blink().unwrap();
+    assert_eq!((stone, none), (Stone::Big(s as u128 * 2024), None));
+    // 2024 followed by 18 zeros has 22 digits, and both halves fit in 64 bits
+    let (l, r) = stone.blink().unwrap();
+    assert_eq!((l, r), (Stone::Small(20240000000), Some(Stone::Small(0))));
+    assert!(Stone::Big(10u128.pow(36)).blink().is_err());
+    // the counts outgrow 128 bits long before 1000 blinks
+    let mut stones = Stones::new([125, 17]);
+    let blinked: Result<Vec<_>> = (0..1000).map(|_| stones.blink()).collect();
+    #[cfg(not(feature = "bigint"))]
+    assert!(blinked.is_err());
+    #[cfg(feature = "bigint")]
+    {
+        blinked.unwrap();
+        assert!(stones.total().unwrap().bits() > 500);
     }
-    println!("{:?}", cache);
-    println!("{}", cache.values().sum::<usize>());
 }
 
 #[test]
@@ -1,37 +1,223 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use regex::Regex;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
 
-fn part1(inputfile: &str) -> Result<i32> {
-    let data = fs::read_to_string(inputfile)?;
-    let re = Regex::new(r"mul\(([0-9]+),([0-9]+)\)")?;
-    let mut total: i32 = 0;
-    for cap in re.captures_iter(&data) {
-        let (_, [a, b]) = cap.extract();
-        let a: i32 = a.parse()?;
-        let b: i32 = b.parse()?;
-        total += a * b;
+const CHUNK_SIZE: usize = 1 << 16;
+
+// Where we are within the instructions being matched. None of the
+// instructions contain 'm' or 'd' after their first character, so on a
+// mismatch the offending byte can simply be rescanned from `Start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    M,
+    Mu,
+    Mul,
+    MulOpen,
+    FirstDigits,
+    Comma,
+    SecondDigits,
+    D,
+    Do,
+    DoOpen,
+    Don,
+    DonQuote,
+    Dont,
+    DontOpen,
+}
+
+// Byte-at-a-time matcher for `mul(A,B)`, `do()` and `don't()`. All state
+// lives in the struct, so instructions can straddle chunk boundaries.
+struct Scanner {
+    state: State,
+    // `None` once an operand has overflowed, which is only an error if the
+    // instruction turns out to be complete
+    a: Option<u64>,
+    b: Option<u64>,
+    doing: bool,
+    total: u64,
+    enabled_total: u64,
+}
+
+fn push_digit(value: Option<u64>, digit: u8) -> Option<u64> {
+    value?.checked_mul(10)?.checked_add((digit - b'0') as u64)
+}
+
+impl Scanner {
+    fn new() -> Scanner {
+        Scanner {
+            state: State::Start,
+            a: Some(0),
+            b: Some(0),
+            doing: true,
+            total: 0,
+            enabled_total: 0,
+        }
+    }
+
+    fn finish_mul(&mut self) -> Result<()> {
+        let (a, b) = self
+            .a
+            .zip(self.b)
+            .ok_or_else(|| anyhow!("mul operand overflows u64"))?;
+        let product = a
+            .checked_mul(b)
+            .ok_or_else(|| anyhow!("mul({a},{b}) overflows u64"))?;
+        self.total = self
+            .total
+            .checked_add(product)
+            .ok_or_else(|| anyhow!("total overflows u64"))?;
+        if self.doing {
+            self.enabled_total = self
+                .enabled_total
+                .checked_add(product)
+                .ok_or_else(|| anyhow!("enabled total overflows u64"))?;
+        }
+        Ok(())
     }
+
+    fn feed(&mut self, byte: u8) -> Result<()> {
+        use State::*;
+        self.state = match (self.state, byte) {
+            (M, b'u') => Mu,
+            (Mu, b'l') => Mul,
+            (Mul, b'(') => MulOpen,
+            (MulOpen, b'0'..=b'9') => {
+                self.a = push_digit(Some(0), byte);
+                FirstDigits
+            }
+            (FirstDigits, b'0'..=b'9') => {
+                self.a = push_digit(self.a, byte);
+                FirstDigits
+            }
+            (FirstDigits, b',') => Comma,
+            (Comma, b'0'..=b'9') => {
+                self.b = push_digit(Some(0), byte);
+                SecondDigits
+            }
+            (SecondDigits, b'0'..=b'9') => {
+                self.b = push_digit(self.b, byte);
+                SecondDigits
+            }
+            (SecondDigits, b')') => {
+                self.finish_mul()?;
+                Start
+            }
+            (D, b'o') => Do,
+            (Do, b'(') => DoOpen,
+            (DoOpen, b')') => {
+                self.doing = true;
+                Start
+            }
+            (Do, b'n') => Don,
+            (Don, b'\'') => DonQuote,
+            (DonQuote, b't') => Dont,
+            (Dont, b'(') => DontOpen,
+            (DontOpen, b')') => {
+                self.doing = false;
+                Start
+            }
+            (_, b'm') => M,
+            (_, b'd') => D,
+            _ => Start,
+        };
+        Ok(())
+    }
+}
+
+// Scan the corrupted memory `chunk_size` bytes at a time, returning the
+// sum of all `mul` instructions and the sum of those enabled by `do()`.
+fn scan<R: Read>(mut reader: R, chunk_size: usize) -> Result<(u64, u64)> {
+    let mut scanner = Scanner::new();
+    let mut buf = vec![0u8; chunk_size];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for &byte in &buf[..n] {
+            scanner.feed(byte)?;
+        }
+    }
+    Ok((scanner.total, scanner.enabled_total))
+}
+
+fn part1(inputfile: &str) -> Result<u64> {
+    let (total, _) = scan(File::open(inputfile)?, CHUNK_SIZE)?;
     Ok(total)
 }
 
-fn part2(inputfile: &str) -> Result<i32> {
-    let data = fs::read_to_string(inputfile)?;
-    let re = Regex::new(r"(mul\(([0-9]+),([0-9]+)\))|(do\(\))|(don't\(\))")?;
+fn part2(inputfile: &str) -> Result<u64> {
+    let (_, enabled_total) = scan(File::open(inputfile)?, CHUNK_SIZE)?;
+    Ok(enabled_total)
+}
+
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    enabled: usize,
+    disabled: usize,
+    switches: usize,
+    near_misses: usize,
+}
+
+// Colour every instruction in `data`: enabled `mul`s green, disabled ones
+// yellow, `do()`/`don't()` cyan and `mul`s that almost parse red.
+fn annotate_str(data: &str) -> Result<(String, Summary)> {
+    let re = Regex::new(
+        r"(mul\(([0-9]+),([0-9]+)\))|(do\(\))|(don't\(\))|(mul\s*[(\[][\s0-9,]*[)\]]?)",
+    )?;
+    let mut out = String::new();
+    let mut summary = Summary::default();
     let mut doing = true;
-    let mut total = 0;
-    for cap in re.captures_iter(&data) {
-        if cap.get(4).is_some() {
+    let mut last = 0;
+    for cap in re.captures_iter(data) {
+        let m = cap.get(0).unwrap();
+        let colour = if cap.get(4).is_some() {
             doing = true;
+            summary.switches += 1;
+            CYAN
         } else if cap.get(5).is_some() {
             doing = false;
+            summary.switches += 1;
+            CYAN
         } else if cap.get(1).is_some() && doing {
-            let a: i32 = cap.get(2).unwrap().as_str().parse()?;
-            let b: i32 = cap.get(3).unwrap().as_str().parse()?;
-            total += a * b;
-        }
+            summary.enabled += 1;
+            GREEN
+        } else if cap.get(1).is_some() {
+            summary.disabled += 1;
+            YELLOW
+        } else {
+            summary.near_misses += 1;
+            RED
+        };
+        out.push_str(&data[last..m.start()]);
+        out.push_str(colour);
+        out.push_str(m.as_str());
+        out.push_str(RESET);
+        last = m.end();
     }
-    Ok(total)
+    out.push_str(&data[last..]);
+    Ok((out, summary))
+}
+
+pub fn annotate(inputfile: &str) -> Result<()> {
+    let data = fs::read_to_string(inputfile)?;
+    let (out, summary) = annotate_str(&data)?;
+    println!("{out}");
+    println!(
+        "{GREEN}enabled{RESET} {} {YELLOW}disabled{RESET} {} {CYAN}do/don't{RESET} {} {RED}near misses{RESET} {}",
+        summary.enabled, summary.disabled, summary.switches, summary.near_misses
+    );
+    Ok(())
 }
 
 #[test]
@@ -44,6 +230,47 @@ fn test_part2() {
     assert_eq!(part2("./input/day3_test_part2.txt").unwrap(), 48);
 }
 
-pub fn run(inputfile: &str) -> Result<(i32, i32)> {
+#[test]
+fn test_scan_chunk_boundaries() {
+    for chunk_size in 1..8 {
+        let file = File::open("./input/day3_test_part2.txt").unwrap();
+        assert_eq!(scan(file, chunk_size).unwrap(), (161, 48));
+    }
+}
+
+#[test]
+fn test_scan_overflow() {
+    let data = format!("mul({},2)", u64::MAX);
+    assert!(scan(data.as_bytes(), CHUNK_SIZE).is_err());
+    let data = format!("mul({}0,2)", u64::MAX);
+    assert!(scan(data.as_bytes(), CHUNK_SIZE).is_err());
+}
+
+#[test]
+fn test_scan_unterminated_overflow() {
+    // too many digits, but never a whole instruction
+    let data = "mul(99999999999999999999999 mul(2,3)mul(3,99999999999999999999999x";
+    assert_eq!(scan(data.as_bytes(), 4).unwrap(), (6, 6));
+}
+
+#[test]
+fn test_annotate() {
+    let data = fs::read_to_string("./input/day3_test_part2.txt").unwrap();
+    let (out, summary) = annotate_str(&data).unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            enabled: 2,
+            disabled: 2,
+            switches: 2,
+            near_misses: 2
+        }
+    );
+    assert!(out.contains(&format!("{RED}mul(32,64]{RESET}")));
+    let (_, summary) = annotate_str("mul(4* mul ( 2 , 4 )").unwrap();
+    assert_eq!(summary.near_misses, 2);
+}
+
+pub fn run(inputfile: &str) -> Result<(u64, u64)> {
     Ok((part1(inputfile)?, part2(inputfile)?))
 }
@@ -1,5 +1,7 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
 use std::fs;
+use std::iter::zip;
 
 fn _print_grid(lines: &Vec<&[u8]>) {
     for line in lines {
@@ -11,78 +13,324 @@ fn _print_grid(lines: &Vec<&[u8]>) {
     }
 }
 
-fn count_words(lines: &Vec<&[u8]>, pattern: &[u8], i: usize, j: usize) -> usize {
-    let w = lines[0].len();
-    let h = lines.len();
-    let mut count = 0;
-    for (dx, dy) in [
-        (1, 0),
-        (-1, 0),
-        (0, 1),
-        (0, -1),
-        (1, 1),
-        (-1, -1),
-        (1, -1),
-        (-1, 1),
-    ] {
-        let mut matched = true;
-        for (k, pat_letter) in pattern.into_iter().enumerate() {
-            let k = k as i32;
-            let x = i as i32 + k * dx;
-            let y = j as i32 + k * dy;
-            if (x < 0)
-                || (x as usize >= w)
-                || (y < 0)
-                || (y as usize >= h)
-                || (lines[x as usize][y as usize] != *pat_letter)
-            {
-                matched = false;
-                break;
+// Split the puzzle into rows, insisting that they all have the same length.
+fn read_lines(data: &str) -> Result<Vec<&[u8]>> {
+    let lines: Vec<&[u8]> = data.lines().map(|s| s.as_bytes()).collect();
+    if let Some(first) = lines.first() {
+        for (idx, line) in lines.iter().enumerate() {
+            if line.len() != first.len() {
+                return Err(anyhow!(
+                    "line {} has length {}, expected {}",
+                    idx + 1,
+                    line.len(),
+                    first.len()
+                ));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+// Cell `k` steps from `start` in direction `dir`, or `None` if that is off
+// the grid. With `wrap` set the grid is a torus, and steps are valid until
+// they come back round to `start` and would reuse its cells.
+fn cell_at(
+    lines: &[&[u8]],
+    start: (usize, usize),
+    dir: (i32, i32),
+    k: usize,
+    wrap: bool,
+) -> Option<(usize, usize)> {
+    let rows = lines.len() as i64;
+    let cols = lines[0].len() as i64;
+    let mut r = start.0 as i64 + k as i64 * dir.0 as i64;
+    let mut c = start.1 as i64 + k as i64 * dir.1 as i64;
+    if wrap {
+        let period = |len: i64, step: i32| if step == 0 { 1 } else { len };
+        let (pr, pc) = (period(rows, dir.0), period(cols, dir.1));
+        if k as i64 >= pr / gcd(pr, pc) * pc {
+            return None;
+        }
+        r = r.rem_euclid(rows);
+        c = c.rem_euclid(cols);
+    }
+    if r < 0 || c < 0 || r >= rows || c >= cols {
+        None
+    } else {
+        Some((r as usize, c as usize))
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (0, 1),
+    (0, -1),
+    (1, 0),
+    (-1, 0),
+    (1, 1),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+];
+
+const HIGHLIGHT: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<u8, usize>,
+    // every dictionary index spelling this node, as words may repeat
+    words: Vec<usize>,
+}
+
+// Prefix tree over the dictionary, so that a single walk from each cell in
+// each direction finds every word starting there.
+struct Trie {
+    nodes: Vec<TrieNode>,
+}
+
+impl Trie {
+    fn new(words: &[&str]) -> Trie {
+        let mut nodes = vec![TrieNode::default()];
+        for (idx, word) in words.iter().enumerate() {
+            let mut node = 0;
+            for &letter in word.as_bytes() {
+                node = match nodes[node].children.get(&letter) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.insert(letter, child);
+                        child
+                    }
+                };
+            }
+            nodes[node].words.push(idx);
+        }
+        Trie { nodes }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Found {
+    word: usize,
+    start: (usize, usize),
+    dir: (i32, i32),
+    len: usize,
+}
+
+fn find_words(lines: &[&[u8]], words: &[&str], wrap: bool) -> Vec<Found> {
+    let trie = Trie::new(words);
+    let mut found = vec![];
+    for i in 0..lines.len() {
+        for j in 0..lines[i].len() {
+            for (d, &(dr, dc)) in DIRECTIONS.iter().enumerate() {
+                let mut node = 0;
+                let mut k = 0;
+                while let Some((r, c)) = cell_at(lines, (i, j), (dr, dc), k, wrap) {
+                    match trie.nodes[node].children.get(&lines[r][c]) {
+                        Some(&child) => node = child,
+                        None => break,
+                    }
+                    k += 1;
+                    // single letters have no direction, so only report them once
+                    if k > 1 || d == 0 {
+                        for &word in &trie.nodes[node].words {
+                            found.push(Found {
+                                word,
+                                start: (i, j),
+                                dir: (dr, dc),
+                                len: k,
+                            });
+                        }
+                    }
+                }
             }
         }
-        if matched {
-            count += 1;
+    }
+    found
+}
+
+fn highlight(lines: &[&[u8]], found: &[Found], wrap: bool) -> String {
+    let mut mask: Vec<Vec<bool>> = lines.iter().map(|line| vec![false; line.len()]).collect();
+    for f in found {
+        for k in 0..f.len {
+            let (r, c) = cell_at(lines, f.start, f.dir, k, wrap).unwrap();
+            mask[r][c] = true;
+        }
+    }
+    let mut out = String::new();
+    for (line, line_mask) in zip(lines, mask) {
+        for (&ch, hit) in zip(line.iter(), line_mask) {
+            if hit {
+                out.push_str(&format!("{HIGHLIGHT}{}{RESET}", ch as char));
+            } else {
+                out.push(ch as char);
+            }
         }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn search(inputfile: &str, words: &[&str], wrap: bool) -> Result<()> {
+    let data = fs::read_to_string(inputfile)?;
+    let lines = read_lines(&data)?;
+    let found = find_words(&lines, words, wrap);
+    for f in &found {
+        println!(
+            "{} at row {} col {} direction {:?}",
+            words[f.word], f.start.0, f.start.1, f.dir
+        );
     }
-    count
+    print!("{}", highlight(&lines, &found, wrap));
+    Ok(())
 }
 
 fn part1(inputfile: &str) -> Result<i32> {
     let data = fs::read_to_string(inputfile)?;
-    let lines: Vec<&[u8]> = data.lines().map(|s| s.as_bytes()).collect();
-    let mut total: usize = 0;
+    let lines = read_lines(&data)?;
     // _print_grid(&lines);
+    Ok(find_words(&lines, &["XMAS"], false).len() as i32)
+}
+
+const X_MAS: &str = "M.S\n.A.\nM.S";
+
+// A small 2D pattern in which '.' matches any letter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stencil {
+    rows: usize,
+    cols: usize,
+    cells: Vec<Option<u8>>,
+}
+
+impl Stencil {
+    fn parse(text: &str) -> Result<Stencil> {
+        let lines: Vec<&[u8]> = text.lines().map(|s| s.as_bytes()).collect();
+        let cols = lines.first().map_or(0, |line| line.len());
+        if cols == 0 || lines.iter().any(|line| line.len() != cols) {
+            return Err(anyhow!("stencil must be a non-empty rectangle"));
+        }
+        let cells = lines
+            .iter()
+            .flat_map(|line| line.iter())
+            .map(|&ch| if ch == b'.' { None } else { Some(ch) })
+            .collect();
+        Ok(Stencil {
+            rows: lines.len(),
+            cols,
+            cells,
+        })
+    }
+
+    fn from_file(stencilfile: &str) -> Result<Stencil> {
+        Stencil::parse(&fs::read_to_string(stencilfile)?)
+    }
+
+    fn get(&self, r: usize, c: usize) -> Option<u8> {
+        self.cells[r * self.cols + c]
+    }
+
+    // quarter turn clockwise
+    fn rotate(&self) -> Stencil {
+        let mut cells = vec![];
+        for r in 0..self.cols {
+            for c in 0..self.rows {
+                cells.push(self.get(self.rows - 1 - c, r));
+            }
+        }
+        Stencil {
+            rows: self.cols,
+            cols: self.rows,
+            cells,
+        }
+    }
+
+    // mirror left to right
+    fn reflect(&self) -> Stencil {
+        let mut cells = vec![];
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                cells.push(self.get(r, self.cols - 1 - c));
+            }
+        }
+        Stencil {
+            rows: self.rows,
+            cols: self.cols,
+            cells,
+        }
+    }
+
+    // The distinct rotations and reflections, so symmetric stencils are
+    // not matched more than once at the same place.
+    fn variants(&self) -> Vec<Stencil> {
+        let mut variants: Vec<Stencil> = vec![];
+        for start in [self.clone(), self.reflect()] {
+            let mut stencil = start;
+            for _ in 0..4 {
+                let next = stencil.rotate();
+                if !variants.contains(&stencil) {
+                    variants.push(stencil);
+                }
+                stencil = next;
+            }
+        }
+        variants
+    }
+
+    fn matches_at(&self, lines: &[&[u8]], i: usize, j: usize) -> bool {
+        if i + self.rows > lines.len() {
+            return false;
+        }
+        (0..self.rows).all(|r| {
+            let line = lines[i + r];
+            j + self.cols <= line.len()
+                && (0..self.cols).all(|c| self.get(r, c).is_none_or(|ch| line[j + c] == ch))
+        })
+    }
+}
+
+// Top-left corner of every match, along with the index of the matching
+// variant from `Stencil::variants`.
+fn find_stencil(lines: &[&[u8]], stencil: &Stencil) -> Vec<(usize, usize, usize)> {
+    let variants = stencil.variants();
+    let mut found = vec![];
     for i in 0..lines.len() {
         for j in 0..lines[i].len() {
-            if lines[i][j] as char == 'X' {
-                total += count_words(&lines, &("XMAS".as_bytes()), i, j);
+            for (v, variant) in variants.iter().enumerate() {
+                if variant.matches_at(lines, i, j) {
+                    found.push((i, j, v));
+                }
             }
         }
     }
-    Ok(total as i32)
+    found
 }
 
-#[rustfmt::skip]
-fn check_mas(lines: &Vec<&[u8]>, i: usize, j: usize) -> bool {
-    let a = ((lines[i - 1][j - 1] == 'M' as u8) && (lines[i + 1][j + 1] == 'S' as u8))
-         || ((lines[i - 1][j - 1] == 'S' as u8) && (lines[i + 1][j + 1] == 'M' as u8));
-    let b = ((lines[i + 1][j - 1] == 'M' as u8) && (lines[i - 1][j + 1] == 'S' as u8))
-         || ((lines[i + 1][j - 1] == 'S' as u8) && (lines[i - 1][j + 1] == 'M' as u8));
-    a && b
+pub fn search_stencil(inputfile: &str, stencilfile: &str) -> Result<()> {
+    let data = fs::read_to_string(inputfile)?;
+    let lines = read_lines(&data)?;
+    let stencil = Stencil::from_file(stencilfile)?;
+    let found = find_stencil(&lines, &stencil);
+    for (i, j, v) in &found {
+        println!("match at row {i} col {j} (variant {v})");
+    }
+    println!("{} matches", found.len());
+    Ok(())
 }
 
 fn part2(inputfile: &str) -> Result<i32> {
     let data = fs::read_to_string(inputfile)?;
-    let lines: Vec<&[u8]> = data.lines().map(|s| s.as_bytes()).collect();
-    let mut total: usize = 0;
-    for i in 1..lines.len() - 1 {
-        for j in 1..lines[i].len() - 1 {
-            if lines[i][j] as char == 'A' && check_mas(&lines, i, j) {
-                total += 1;
-            }
-        }
-    }
-    Ok(total as i32)
+    let lines = read_lines(&data)?;
+    let stencil = Stencil::parse(X_MAS)?;
+    Ok(find_stencil(&lines, &stencil).len() as i32)
 }
 
 #[test]
@@ -95,6 +343,63 @@ fn test_part2() {
     assert_eq!(part2("./input/day4_test.txt").unwrap(), 9);
 }
 
+#[test]
+fn test_find_words() {
+    let data = fs::read_to_string("./input/day4_test.txt").unwrap();
+    let lines = read_lines(&data).unwrap();
+    let found = find_words(&lines, &["XMAS", "MAS", "X"], false);
+    let count = |word| found.iter().filter(|f| f.word == word).count();
+    assert_eq!(count(0), 18);
+    assert_eq!(count(2), data.matches('X').count());
+    assert!(found.contains(&Found {
+        word: 0,
+        start: (0, 5),
+        dir: (0, 1),
+        len: 4
+    }));
+    let found = find_words(&lines, &["XMAS", "SAMX", "XMAS"], false);
+    assert_eq!(count(0), 18);
+    assert_eq!(found.iter().filter(|f| f.word == 2).count(), 18);
+}
+
+#[test]
+fn test_stencil_variants() {
+    assert_eq!(Stencil::parse(X_MAS).unwrap().variants().len(), 4);
+    assert_eq!(Stencil::parse("AB\nC.").unwrap().variants().len(), 8);
+    assert_eq!(Stencil::parse("A.\n.A").unwrap().variants().len(), 2);
+    assert!(Stencil::parse("AB\nC").is_err());
+}
+
+#[test]
+fn test_rectangular_and_wrap() {
+    let lines = read_lines("XMASXMAS\nSAMXSAMX").unwrap();
+    assert_eq!(find_words(&lines, &["XMAS"], false).len(), 4);
+    let lines = read_lines("MASX\nAAAA").unwrap();
+    assert_eq!(find_words(&lines, &["XMAS"], false).len(), 0);
+    let found = find_words(&lines, &["XMAS"], true);
+    assert_eq!(
+        found,
+        vec![Found {
+            word: 0,
+            start: (0, 3),
+            dir: (0, 1),
+            len: 4
+        }]
+    );
+    assert!(read_lines("XMAS\nXMA\n").is_err());
+}
+
+#[test]
+fn test_wrap_cycle() {
+    let lines = read_lines("XMAS").unwrap();
+    assert!(find_words(&lines, &["XMASX", "XX"], true).is_empty());
+    // along the row and both diagonals, which on one row visit the same cells
+    assert_eq!(find_words(&lines, &["SXMA"], true).len(), 3);
+    let lines = read_lines("X\nM\nA\nS").unwrap();
+    assert_eq!(find_words(&lines, &["MASX"], true).len(), 3);
+    assert!(find_words(&lines, &["MASXM"], true).is_empty());
+}
+
 pub fn run(inputfile: &str) -> Result<(i32, i32)> {
     Ok((part1(inputfile)?, part2(inputfile)?))
 }
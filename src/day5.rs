@@ -1,20 +1,210 @@
-use anyhow::Result;
+#[cfg(test)]
+use crate::testing::Lcg;
+use anyhow::{anyhow, Result};
+use petgraph::algo::{has_path_connecting, tarjan_scc, toposort};
+use petgraph::graph::{DiGraph, EdgeReference, NodeIndex};
+use petgraph::visit::{EdgeFiltered, EdgeRef};
 use regex::Regex;
-use std::cmp::Ordering::*;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 
-fn parts_12(inputfile: &str) -> Result<(i32, i32)> {
+// Pages below this get a row in the bit matrix
+const MATRIX_PAGES: usize = 128;
+
+// Constant-time lookup of rules: bit Y of row X is set for rule `X|Y`
+// when all pages are small, otherwise a hash set of the pairs.
+enum RuleIndex {
+    Matrix(Vec<u128>),
+    Set(HashSet<(usize, usize)>),
+}
+
+impl RuleIndex {
+    fn new(pairs: &[(usize, usize)]) -> RuleIndex {
+        if pairs
+            .iter()
+            .all(|&(a, b)| a < MATRIX_PAGES && b < MATRIX_PAGES)
+        {
+            let mut rows = vec![0u128; MATRIX_PAGES];
+            for &(a, b) in pairs {
+                rows[a] |= 1 << b;
+            }
+            RuleIndex::Matrix(rows)
+        } else {
+            RuleIndex::Set(pairs.iter().copied().collect())
+        }
+    }
+
+    fn contains(&self, a: usize, b: usize) -> bool {
+        match self {
+            RuleIndex::Matrix(rows) => {
+                a < MATRIX_PAGES && b < MATRIX_PAGES && (rows[a] >> b) & 1 == 1
+            }
+            RuleIndex::Set(set) => set.contains(&(a, b)),
+        }
+    }
+}
+
+// The page ordering rules, with an edge X -> Y for every rule `X|Y`.
+struct Rules {
+    graph: DiGraph<usize, ()>,
+    nodes: HashMap<usize, NodeIndex>,
+    index: RuleIndex,
+}
+
+impl Rules {
+    fn new(pairs: &[(usize, usize)]) -> Rules {
+        let mut graph = DiGraph::new();
+        let mut nodes = HashMap::new();
+        let mut seen = HashSet::new();
+        for &(first, second) in pairs {
+            let a = *nodes.entry(first).or_insert_with(|| graph.add_node(first));
+            let b = *nodes
+                .entry(second)
+                .or_insert_with(|| graph.add_node(second));
+            if seen.insert((first, second)) {
+                graph.add_edge(a, b, ());
+            }
+        }
+        Rules {
+            graph,
+            nodes,
+            index: RuleIndex::new(pairs),
+        }
+    }
+
+    // Is there a rule saying `a` must come before `b`?
+    fn before(&self, a: usize, b: usize) -> bool {
+        self.index.contains(a, b)
+    }
+
+    // A page with a rule `X|X` can't be placed anywhere, so an update
+    // containing it is never in order.
+    fn is_ordered(&self, update: &[usize]) -> bool {
+        (0..update.len()).all(|i| (i..update.len()).all(|j| !self.before(update[j], update[i])))
+    }
+
+    // Sort `update` by a topological sort of the rules restricted to its
+    // pages, failing if those rules contain a cycle. A rule `X|X` is a
+    // cycle of its own.
+    fn order(&self, update: &[usize]) -> Result<Vec<usize>> {
+        let mut sub = DiGraph::<usize, ()>::new();
+        let sub_nodes: Vec<NodeIndex> = update.iter().map(|&page| sub.add_node(page)).collect();
+        for (i, &a) in update.iter().enumerate() {
+            for (j, &b) in update.iter().enumerate() {
+                if (i != j || a == b) && self.before(a, b) {
+                    sub.add_edge(sub_nodes[i], sub_nodes[j], ());
+                }
+            }
+        }
+        match toposort(&sub, None) {
+            Ok(sorted) => Ok(sorted.into_iter().map(|node| sub[node]).collect()),
+            Err(_) => {
+                let cycles: Vec<Vec<usize>> = tarjan_scc(&sub)
+                    .into_iter()
+                    .filter(|scc| scc.len() > 1 || sub.contains_edge(scc[0], scc[0]))
+                    .map(|scc| {
+                        let mut pages: Vec<usize> = scc.into_iter().map(|node| sub[node]).collect();
+                        pages.sort_unstable();
+                        pages
+                    })
+                    .collect();
+                Err(anyhow!(
+                    "rules for update {:?} contain a cycle among pages {:?}",
+                    update,
+                    cycles
+                ))
+            }
+        }
+    }
+}
+
+// A rule `X|Y` broken by an update, with the positions of X and Y in it.
+#[derive(Debug, PartialEq, Eq)]
+struct Violation {
+    rule: (usize, usize),
+    positions: (usize, usize),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Analysis {
+    // pairs with both `X|Y` and `Y|X`, and pages with `X|X`
+    contradictions: Vec<(usize, usize)>,
+    // groups of pages whose rules form a cycle, however long
+    cycles: Vec<Vec<usize>>,
+    // rules outside those cycles already implied by a chain of other rules
+    redundant: Vec<(usize, usize)>,
+    // pages in updates which no rule mentions
+    unconstrained: Vec<usize>,
+}
+
+impl Rules {
+    fn violations(&self, update: &[usize]) -> Vec<Violation> {
+        let mut violations = vec![];
+        for i in 0..update.len() {
+            for j in i + 1..update.len() {
+                if self.before(update[j], update[i]) {
+                    violations.push(Violation {
+                        rule: (update[j], update[i]),
+                        positions: (j, i),
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    fn analyse(&self, updates: &[Vec<usize>]) -> Analysis {
+        let mut analysis = Analysis::default();
+        let mut cyclic = HashSet::new();
+        for scc in tarjan_scc(&self.graph) {
+            if scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]) {
+                cyclic.extend(scc.iter().copied());
+                let mut pages: Vec<usize> = scc.into_iter().map(|node| self.graph[node]).collect();
+                pages.sort_unstable();
+                analysis.cycles.push(pages);
+            }
+        }
+        analysis.cycles.sort_unstable();
+        for edge in self.graph.edge_references() {
+            let (a, b) = (self.graph[edge.source()], self.graph[edge.target()]);
+            if a <= b && self.before(b, a) {
+                analysis.contradictions.push((a, b));
+            }
+        }
+        analysis.contradictions.sort_unstable();
+        // every order breaks some rule in a cycle, so only look for implied
+        // rules among the pages which can be ordered
+        let acyclic =
+            |e: &EdgeReference<()>| !cyclic.contains(&e.source()) && !cyclic.contains(&e.target());
+        for edge in self.graph.edge_references().filter(acyclic) {
+            let (a, b) = (self.graph[edge.source()], self.graph[edge.target()]);
+            let others = EdgeFiltered::from_fn(&self.graph, |e| e.id() != edge.id() && acyclic(&e));
+            if has_path_connecting(&others, edge.source(), edge.target(), None) {
+                analysis.redundant.push((a, b));
+            }
+        }
+        let mut seen = HashSet::new();
+        for &page in updates.iter().flatten() {
+            if !self.nodes.contains_key(&page) && seen.insert(page) {
+                analysis.unconstrained.push(page);
+            }
+        }
+        analysis
+    }
+}
+
+fn read_input(inputfile: &str) -> Result<(Rules, Vec<Vec<usize>>)> {
     let data = fs::read_to_string(inputfile)?;
     let parts: Vec<_> = data.split("\n\n").collect();
     assert!(parts.len() == 2);
 
     let re = Regex::new(r"([0-9]+)\|([0-9]+)")?;
-    let mut rules: Vec<(usize, usize)> = vec![];
+    let mut pairs: Vec<(usize, usize)> = vec![];
     for cap in re.captures_iter(parts[0]) {
         let (_, [first, second]) = cap.extract();
         let first: usize = first.parse().unwrap();
         let second: usize = second.parse().unwrap();
-        rules.push((first, second));
+        pairs.push((first, second));
     }
 
     let updates: Vec<Vec<usize>> = parts[1]
@@ -25,36 +215,196 @@ fn parts_12(inputfile: &str) -> Result<(i32, i32)> {
                 .collect()
         })
         .collect();
+    Ok((Rules::new(&pairs), updates))
+}
+
+fn parts_12(inputfile: &str) -> Result<(i32, i32)> {
+    let (rules, updates) = read_input(inputfile)?;
 
     let mut part1_total = 0;
     let mut part2_total = 0;
     for update in &updates {
-        let mut new_update: Vec<usize> = update.clone();
-        new_update.sort_by(|a, b| {
-            if rules.contains(&(*a, *b)) {
-                Less
-            } else if rules.contains(&(*b, *a)) {
-                Greater
-            } else {
-                Equal
-            }
-        });
-        let valid = new_update == *update;
-        println!("{valid} {update:?} {new_update:?}");
+        let valid = rules.is_ordered(update);
         if valid {
+            println!("{valid} {update:?}");
             part1_total += update[update.len() / 2];
         } else {
+            let new_update = rules.order(update)?;
+            println!("{valid} {update:?} {new_update:?}");
             part2_total += new_update[new_update.len() / 2];
         }
     }
     Ok((part1_total as i32, part2_total as i32))
 }
 
+pub fn explain(inputfile: &str) -> Result<()> {
+    let (rules, updates) = read_input(inputfile)?;
+    for update in &updates {
+        let violations = rules.violations(update);
+        if violations.is_empty() {
+            continue;
+        }
+        println!("{update:?} breaks:");
+        for v in violations {
+            println!(
+                "  {}|{} ({} at position {}, {} at position {})",
+                v.rule.0, v.rule.1, v.rule.0, v.positions.0, v.rule.1, v.positions.1
+            );
+        }
+    }
+    let analysis = rules.analyse(&updates);
+    println!("contradictory rules {:?}", analysis.contradictions);
+    println!("pages in rule cycles {:?}", analysis.cycles);
+    println!("redundant rules {:?}", analysis.redundant);
+    println!("unconstrained pages {:?}", analysis.unconstrained);
+    Ok(())
+}
+
 #[test]
 fn test_parts() {
     assert_eq!(parts_12("./input/day5_test.txt").unwrap(), (143, 123));
 }
 
+#[test]
+fn test_cycle() {
+    let rules = Rules::new(&[(1, 2), (2, 3), (3, 1), (4, 1)]);
+    assert_eq!(rules.order(&[4, 2, 1]).unwrap(), vec![4, 1, 2]);
+    assert_eq!(
+        rules.order(&[3, 4, 2, 1]).unwrap_err().to_string(),
+        "rules for update [3, 4, 2, 1] contain a cycle among pages [[1, 2, 3]]"
+    );
+    let rules = Rules::new(&[(1, 2), (3, 3)]);
+    assert_eq!(rules.order(&[2, 1]).unwrap(), vec![1, 2]);
+    assert!(rules.is_ordered(&[1, 2]));
+    assert!(!rules.is_ordered(&[1, 3]));
+    assert_eq!(
+        rules.order(&[2, 3, 1]).unwrap_err().to_string(),
+        "rules for update [2, 3, 1] contain a cycle among pages [[3]]"
+    );
+}
+
+#[test]
+fn test_explain() {
+    let (rules, _) = read_input("./input/day5_test.txt").unwrap();
+    assert_eq!(
+        rules.violations(&[75, 97, 47, 61, 53]),
+        vec![Violation {
+            rule: (97, 75),
+            positions: (1, 0)
+        }]
+    );
+    let rules = Rules::new(&[(1, 2), (2, 3), (1, 3), (4, 5), (5, 4)]);
+    assert_eq!(
+        rules.analyse(&[vec![1, 2, 3], vec![6, 1, 6]]),
+        Analysis {
+            contradictions: vec![(4, 5)],
+            cycles: vec![vec![4, 5]],
+            redundant: vec![(1, 3)],
+            unconstrained: vec![6]
+        }
+    );
+    let rules = Rules::new(&[(1, 2), (2, 1), (1, 3)]);
+    assert!(rules.analyse(&[]).redundant.is_empty());
+    // a longer cycle and a self-rule, with a shortcut across the cycle
+    let rules = Rules::new(&[
+        (1, 2),
+        (2, 3),
+        (3, 1),
+        (1, 3),
+        (7, 7),
+        (4, 5),
+        (5, 6),
+        (4, 6),
+    ]);
+    let analysis = rules.analyse(&[]);
+    assert_eq!(analysis.contradictions, vec![(1, 3), (7, 7)]);
+    assert_eq!(analysis.cycles, vec![vec![1, 2, 3], vec![7]]);
+    assert_eq!(analysis.redundant, vec![(4, 6)]);
+}
+
+// Rules i|j for random pairs i < j of `n_pages` pages, and updates of
+// `update_len` random pages.
+#[cfg(test)]
+fn generate(
+    n_pages: usize,
+    n_rules: usize,
+    n_updates: usize,
+    update_len: usize,
+) -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
+    let mut lcg = Lcg::new();
+    let mut next = |n: usize| lcg.below(n);
+    let mut pairs = vec![];
+    while pairs.len() < n_rules {
+        let (a, b) = (next(n_pages), next(n_pages));
+        if a != b {
+            pairs.push((a.min(b), a.max(b)));
+        }
+    }
+    let updates = (0..n_updates)
+        .map(|_| {
+            let mut pages: Vec<usize> = (0..n_pages).collect();
+            for i in 0..update_len {
+                let j = i + next(n_pages - i);
+                pages.swap(i, j);
+            }
+            pages.truncate(update_len);
+            pages
+        })
+        .collect();
+    (pairs, updates)
+}
+
+// Compare checking every pair of pages in each update against the rules
+// using `Vec::contains` with the indexed lookup.
+#[test]
+#[ignore]
+fn bench_rule_lookup() {
+    use std::time::Instant;
+
+    for (n_pages, n_rules) in [(100, 4000), (1000, 50000)] {
+        let (pairs, updates) = generate(n_pages, n_rules, 100, 90);
+        let rules = Rules::new(&pairs);
+
+        let start = Instant::now();
+        let slow: Vec<bool> = updates
+            .iter()
+            .map(|update| {
+                (0..update.len()).all(|i| {
+                    (i + 1..update.len()).all(|j| !pairs.contains(&(update[j], update[i])))
+                })
+            })
+            .collect();
+        let slow_time = start.elapsed();
+
+        let start = Instant::now();
+        let fast: Vec<bool> = updates.iter().map(|u| rules.is_ordered(u)).collect();
+        let fast_time = start.elapsed();
+
+        let start = Instant::now();
+        for update in &updates {
+            rules.order(update).unwrap();
+        }
+        let topo_time = start.elapsed();
+
+        assert_eq!(slow, fast);
+        println!(
+            "{n_pages} pages {n_rules} rules: Vec::contains {slow_time:?}, indexed {fast_time:?}, toposort {topo_time:?}"
+        );
+    }
+}
+
+#[test]
+fn test_rule_index() {
+    let (pairs, _) = generate(1000, 5000, 0, 0);
+    let big = Rules::new(&pairs);
+    let small = Rules::new(&[(97, 13), (1, 127)]);
+    assert!(matches!(big.index, RuleIndex::Set(_)));
+    assert!(matches!(small.index, RuleIndex::Matrix(_)));
+    assert!(big.before(pairs[0].0, pairs[0].1));
+    assert!(small.before(97, 13) && small.before(1, 127));
+    assert!(!small.before(13, 97) && !small.before(500, 13));
+}
+
 pub fn run(inputfile: &str) -> Result<(i32, i32)> {
     Ok(parts_12(inputfile)?)
 }
@@ -1,7 +1,5 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use grid::Grid;
-use indicatif::ProgressBar;
-use std::collections::HashMap;
 use std::fs;
 // use std::io;
 // use std::io::prelude::*;
@@ -21,126 +19,504 @@ fn _print_grid(grid: &Grid<char>) {
     }
 }
 
-fn char_to_dir(ch: char) -> (isize, isize) {
-    match ch {
-        '^' => (-1, 0),
-        '>' => (0, 1),
-        'v' => (1, 0),
-        '<' => (0, -1),
-        _ => (0, 0),
-    }
-}
-
-fn step(grid: &mut Grid<char>, guard: Option<(usize, usize)>) -> Option<(usize, usize)> {
-    let directions = vec!['^', '>', 'v', '<'];
-    let guard = guard.unwrap();
-    let mut guard_ch = *grid.get(guard.0, guard.1).unwrap();
-    let mut dir = char_to_dir(guard_ch);
-
-    let (new_guard, new_guard_ch) =
-        match grid.get(guard.0 as isize + dir.0, guard.1 as isize + dir.1) {
-            Some('.') | Some('X') => (
-                Some((
-                    (guard.0 as isize + dir.0) as usize,
-                    (guard.1 as isize + dir.1) as usize,
-                )),
-                guard_ch,
-            ),
-            Some('#') => {
-                while *grid
-                    .get(guard.0 as isize + dir.0, guard.1 as isize + dir.1)
-                    .unwrap()
-                    == '#'
-                {
-                    guard_ch = directions
-                        [(directions.iter().position(|&ch| ch == guard_ch).unwrap() + 1) % 4];
-                    dir = char_to_dir(guard_ch);
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+const HEADINGS: [char; 4] = ['^', '>', 'v', '<'];
+
+// Which way the guard turns on meeting an obstacle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    Right,
+    Left,
+    Back,
+}
+
+impl Turn {
+    fn quarter_turns(self) -> usize {
+        match self {
+            Turn::Right => 1,
+            Turn::Back => 2,
+            Turn::Left => 3,
+        }
+    }
+}
+
+// What happens when the guard walks off the edge of the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Leave,
+    Wrap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardRules {
+    pub turn: Turn,
+    pub obstacles: Vec<char>,
+    pub exit: Exit,
+}
+
+impl Default for GuardRules {
+    fn default() -> GuardRules {
+        GuardRules {
+            turn: Turn::Right,
+            obstacles: vec!['#'],
+            exit: Exit::Leave,
+        }
+    }
+}
+
+impl GuardRules {
+    // Parse `--turn right|left|back`, `--obstacles CHARS` and `--wrap`,
+    // allowing the other day 6 options `--patrol` and `--loops [ROW,COL]`.
+    pub fn from_options(options: &[String]) -> Result<GuardRules> {
+        let mut rules = GuardRules::default();
+        let mut options = options.iter().peekable();
+        while let Some(option) = options.next() {
+            let mut value = || {
+                options
+                    .next_if(|value| !value.starts_with("--"))
+                    .ok_or_else(|| anyhow!("{option} needs a value"))
+            };
+            match option.as_str() {
+                "--turn" => {
+                    rules.turn = match value()?.as_str() {
+                        "right" => Turn::Right,
+                        "left" => Turn::Left,
+                        "back" => Turn::Back,
+                        other => return Err(anyhow!("bad turn {other:?}")),
+                    }
+                }
+                "--obstacles" => rules.obstacles = value()?.chars().collect(),
+                "--wrap" => rules.exit = Exit::Wrap,
+                "--patrol" => (),
+                "--loops" => {
+                    // the cell to draw is optional
+                    let _ = value();
                 }
-                (
-                    Some((
-                        (guard.0 as isize + dir.0) as usize,
-                        (guard.1 as isize + dir.1) as usize,
-                    )),
-                    guard_ch,
-                )
+                _ => return Err(anyhow!("unknown option {option}")),
             }
-            _ => (None, guard_ch),
-        };
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Guard {
+    pos: (usize, usize),
+    dir: usize,
+}
+
+fn find_guards(grid: &Grid<char>) -> Vec<Guard> {
+    grid.indexed_iter()
+        .filter_map(|(pos, ch)| {
+            let dir = HEADINGS.iter().position(|h| h == ch)?;
+            Some(Guard { pos, dir })
+        })
+        .collect()
+}
 
-    grid[(guard.0, guard.1)] = 'X';
-    if new_guard.is_some() {
-        assert!(grid[(new_guard.unwrap().0, new_guard.unwrap().1)] != '#');
-        grid[(new_guard.unwrap().0, new_guard.unwrap().1)] = new_guard_ch;
+// Where a guard walking straight ahead stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+    // in front of an obstacle, at this cell
+    Stop(usize),
+    // off the edge of the map
+    Leave,
+    // round and round a row or column with no obstacles on a wrapped map
+    Endless,
+}
+
+// The cells of each row or column in the order a guard heading in
+// direction `dir` walks them.
+fn lines(rows: usize, cols: usize, dir: usize) -> Vec<Vec<usize>> {
+    match DIRECTIONS[dir] {
+        (0, dc) => (0..rows)
+            .map(|r| {
+                let mut line: Vec<usize> = (0..cols).map(|c| r * cols + c).collect();
+                if dc < 0 {
+                    line.reverse();
+                }
+                line
+            })
+            .collect(),
+        (dr, _) => (0..cols)
+            .map(|c| {
+                let mut line: Vec<usize> = (0..rows).map(|r| r * cols + c).collect();
+                if dr < 0 {
+                    line.reverse();
+                }
+                line
+            })
+            .collect(),
     }
-    new_guard
 }
 
-fn part1(inputfile: &str) -> Result<usize> {
-    let mut grid = read_grid(inputfile)?;
-    let mut guard = None;
-    for ((row, col), &val) in grid.indexed_iter() {
-        if val == '^' {
-            guard = Some((row, col));
-            break;
+// Fill in the jumps from each cell of one line, working backwards from an
+// obstacle (or the edge) so the jump from the cell ahead is always known.
+fn fill_jumps(line: &[usize], obstacles: &[bool], exit: Exit, jump: &mut [Jump]) {
+    let n = line.len();
+    let ahead = |jump: &[Jump], idx: usize, next: usize| {
+        if obstacles[next] {
+            Jump::Stop(line[idx])
+        } else {
+            jump[next]
+        }
+    };
+    match (exit, line.iter().position(|&cell| obstacles[cell])) {
+        (Exit::Leave, _) => {
+            for idx in (0..n).rev() {
+                jump[line[idx]] = match line.get(idx + 1) {
+                    Some(&next) => ahead(jump, idx, next),
+                    None => Jump::Leave,
+                };
+            }
+        }
+        (Exit::Wrap, None) => line.iter().for_each(|&cell| jump[cell] = Jump::Endless),
+        (Exit::Wrap, Some(obstacle)) => {
+            for back in 1..=n {
+                let idx = (obstacle + n - back) % n;
+                jump[line[idx]] = ahead(jump, idx, line[(idx + 1) % n]);
+            }
+        }
+    }
+}
+
+// The lab as flat arrays indexed by `row * cols + col`, with jump tables
+// so the guard can move from one obstacle to the next in a single step.
+// Every way of following guards goes through this, with the obstacles,
+// turns and edges given by the `GuardRules`.
+struct Lab {
+    rows: usize,
+    cols: usize,
+    obstacles: Vec<bool>,
+    // quarter turns made on meeting an obstacle
+    turn: usize,
+    exit: Exit,
+    // jumps[dir][cell] is where a guard walking from `cell` in direction
+    // `dir` stops
+    jumps: [Vec<Jump>; 4],
+}
+
+impl Lab {
+    fn new(grid: &Grid<char>, rules: &GuardRules) -> Lab {
+        let (rows, cols) = (grid.rows(), grid.cols());
+        let obstacles: Vec<bool> = grid.iter().map(|ch| rules.obstacles.contains(ch)).collect();
+        let mut jumps: [Vec<Jump>; 4] = Default::default();
+        for (dir, jump) in jumps.iter_mut().enumerate() {
+            *jump = vec![Jump::Leave; rows * cols];
+            for line in lines(rows, cols, dir) {
+                fill_jumps(&line, &obstacles, rules.exit, jump);
+            }
+        }
+        Lab {
+            rows,
+            cols,
+            obstacles,
+            turn: rules.turn.quarter_turns(),
+            exit: rules.exit,
+            jumps,
+        }
+    }
+
+    fn cell(&self, guard: &Guard) -> usize {
+        guard.pos.0 * self.cols + guard.pos.1
+    }
+
+    // The cell `k` steps from `pos` in direction `dir`, or `None` if that
+    // is off the map.
+    fn advance(&self, pos: usize, dir: usize, k: usize) -> Option<usize> {
+        let (rows, cols) = (self.rows as isize, self.cols as isize);
+        let r = (pos / self.cols) as isize + k as isize * DIRECTIONS[dir].0;
+        let c = (pos % self.cols) as isize + k as isize * DIRECTIONS[dir].1;
+        match self.exit {
+            Exit::Wrap => Some((r.rem_euclid(rows) * cols + c.rem_euclid(cols)) as usize),
+            Exit::Leave if r < 0 || c < 0 || r >= rows || c >= cols => None,
+            Exit::Leave => Some((r * cols + c) as usize),
+        }
+    }
+
+    // Number of steps from `from` to `to` walking in direction `dir`, if
+    // `to` is straight ahead.
+    fn distance(&self, from: usize, to: usize, dir: usize) -> Option<usize> {
+        let (fr, fc) = (from / self.cols, from % self.cols);
+        let (tr, tc) = (to / self.cols, to % self.cols);
+        let (k, len) = match DIRECTIONS[dir] {
+            (0, _) if fr != tr => return None,
+            (_, 0) if fc != tc => return None,
+            (0, dc) => ((tc as isize - fc as isize) * dc, self.cols),
+            (dr, _) => ((tr as isize - fr as isize) * dr, self.rows),
+        };
+        let k = match self.exit {
+            Exit::Wrap => k.rem_euclid(len as isize),
+            Exit::Leave => k,
+        };
+        (k > 0).then_some(k as usize)
+    }
+
+    // Where the guard stops walking from `pos` in direction `dir`, taking
+    // account of an extra obstacle at `block` which may cut the jump short.
+    fn jump(&self, pos: usize, dir: usize, block: Option<usize>) -> Jump {
+        let target = self.jumps[dir][pos];
+        if let Some(k) = block.and_then(|b| self.distance(pos, b, dir)) {
+            let reach = match target {
+                Jump::Stop(t) if t == pos => Some(0),
+                Jump::Stop(t) => self.distance(pos, t, dir),
+                Jump::Leave | Jump::Endless => None,
+            };
+            if reach.is_none_or(|reach| k <= reach) {
+                return Jump::Stop(self.advance(pos, dir, k - 1).unwrap());
+            }
+        }
+        target
+    }
+
+    // Every cell the guard visits, and whether they end up in a loop
+    // rather than leaving the map.
+    fn patrol_path(&self, start: usize, dir: usize) -> (Vec<usize>, bool) {
+        let mut visited = vec![false; self.rows * self.cols];
+        let mut seen = vec![false; 4 * self.rows * self.cols];
+        let mut path = vec![];
+        let (mut pos, mut dir) = (start, dir);
+        loop {
+            let stop = self.jumps[dir][pos];
+            let mut cell = pos;
+            while stop != Jump::Stop(cell) {
+                if !visited[cell] {
+                    visited[cell] = true;
+                    path.push(cell);
+                }
+                cell = match self.advance(cell, dir, 1) {
+                    None => return (path, false),
+                    // all the way round an empty row or column
+                    Some(next) if next == pos => return (path, true),
+                    Some(next) => next,
+                };
+            }
+            if !visited[cell] {
+                visited[cell] = true;
+                path.push(cell);
+            }
+            if seen[4 * pos + dir] {
+                return (path, true);
+            }
+            seen[4 * pos + dir] = true;
+            pos = cell;
+            dir = (dir + self.turn) % 4;
         }
     }
-    // println!("Guard is at {guard:?}");
-    while guard.is_some() {
-        guard = step(&mut grid, guard);
-        // print_grid(&grid);
+
+    // Does the guard get stuck in a loop with an extra obstacle at `block`?
+    // `seen` holds the turn at which each (cell, direction) was last
+    // visited, so it need not be cleared between calls with new `stamp`s.
+    fn loops(&self, start: usize, dir: usize, block: usize, seen: &mut [u32], stamp: u32) -> bool {
+        let (mut pos, mut dir) = (start, dir);
+        loop {
+            if seen[4 * pos + dir] == stamp {
+                return true;
+            }
+            seen[4 * pos + dir] = stamp;
+            match self.jump(pos, dir, Some(block)) {
+                Jump::Stop(stop) => {
+                    pos = stop;
+                    dir = (dir + self.turn) % 4;
+                }
+                Jump::Leave => return false,
+                Jump::Endless => return true,
+            }
+        }
+    }
+}
+
+// Follow each guard independently until they leave the map or repeat
+// themselves, returning the cells visited by any guard and, for each
+// guard, whether they ended up in a loop.
+fn patrol(grid: &Grid<char>, rules: &GuardRules) -> (Grid<bool>, Vec<bool>) {
+    let lab = Lab::new(grid, rules);
+    let mut visited = Grid::<bool>::new(grid.rows(), grid.cols());
+    let mut looped = vec![];
+    for guard in find_guards(grid) {
+        let (path, guard_looped) = lab.patrol_path(lab.cell(&guard), guard.dir);
+        for cell in path {
+            visited[(cell / lab.cols, cell % lab.cols)] = true;
+        }
+        looped.push(guard_looped);
     }
-    let npos = grid.iter().filter(|&c| *c == 'X').count();
+    (visited, looped)
+}
+
+fn part1(inputfile: &str) -> Result<usize> {
+    let grid = read_grid(inputfile)?;
+    let (visited, _) = patrol(&grid, &GuardRules::default());
+    let npos = visited.iter().filter(|&&v| v).count();
     println!("{npos}");
     Ok(npos)
 }
 
-fn part2(inputfile: &str) -> Result<i32> {
-    let orig_grid = read_grid(inputfile)?;
-    let mut orig_guard = None;
-    for ((row, col), &val) in orig_grid.indexed_iter() {
-        if val == '^' {
-            orig_guard = Some((row, col));
-            break;
+pub fn explore(inputfile: &str, rules: &GuardRules) -> Result<()> {
+    let grid = read_grid(inputfile)?;
+    let (visited, looped) = patrol(&grid, rules);
+    for (guard, looped) in find_guards(&grid).iter().zip(looped) {
+        println!(
+            "guard {} at {:?} {}",
+            HEADINGS[guard.dir],
+            guard.pos,
+            if looped { "loops" } else { "leaves" }
+        );
+    }
+    println!(
+        "{} positions visited",
+        visited.iter().filter(|&&v| v).count()
+    );
+    Ok(())
+}
+
+// The cycle a guard ends up walking forever: `steps` holds each cell
+// entered, with the heading the guard had on entering it, starting from
+// the first cell of the cycle to be reached.
+#[derive(Debug, PartialEq, Eq)]
+struct Loop {
+    entry: (usize, usize, char),
+    steps: Vec<(usize, usize, char)>,
+}
+
+impl Loop {
+    fn len(&self) -> usize {
+        self.steps.len()
+    }
+}
+
+impl Lab {
+    fn blocked(&self, cell: usize, block: Option<usize>) -> bool {
+        self.obstacles[cell] || Some(cell) == block
+    }
+
+    // Walk the guard one cell at a time with an extra obstacle at `block`,
+    // returning the loop they get stuck in, if any.
+    fn find_loop(&self, start: usize, dir: usize, block: Option<usize>) -> Option<Loop> {
+        // index into `states` at which each (cell, direction) was reached
+        let mut seen: Vec<Option<usize>> = vec![None; 4 * self.rows * self.cols];
+        let mut states: Vec<(usize, usize)> = vec![(start, dir)];
+        seen[4 * start + dir] = Some(0);
+        let (mut pos, mut dir) = (start, dir);
+        let mut turns = 0;
+        let first = loop {
+            let next = self.advance(pos, dir, 1)?;
+            if self.blocked(next, block) {
+                dir = (dir + self.turn) % 4;
+                turns += 1;
+                // boxed in on all four sides
+                if turns == 4 {
+                    break states.len() - 1;
+                }
+                continue;
+            }
+            turns = 0;
+            pos = next;
+            if let Some(idx) = seen[4 * pos + dir] {
+                break idx;
+            }
+            seen[4 * pos + dir] = Some(states.len());
+            states.push((pos, dir));
+        };
+        let steps: Vec<(usize, usize, char)> = states[first..]
+            .iter()
+            .map(|&(cell, dir)| (cell / self.cols, cell % self.cols, HEADINGS[dir]))
+            .collect();
+        Some(Loop {
+            entry: steps[0],
+            steps,
+        })
+    }
+}
+
+// Draw the loop over the lab as in the puzzle description, with `|` and
+// `-` for vertical and horizontal moves, `+` where they meet and the extra
+// obstacle as `O`.
+fn draw_loop(grid: &Grid<char>, block: (usize, usize), guard_loop: &Loop) -> Grid<char> {
+    let mut vertical = Grid::<bool>::new(grid.rows(), grid.cols());
+    let mut horizontal = Grid::<bool>::new(grid.rows(), grid.cols());
+    let n = guard_loop.len();
+    for (idx, &(r, c, heading)) in guard_loop.steps.iter().enumerate() {
+        // the heading leaving this cell is the one entering the next
+        let (_, _, next_heading) = guard_loop.steps[(idx + 1) % n];
+        for h in [heading, next_heading] {
+            match h {
+                '^' | 'v' => vertical[(r, c)] = true,
+                _ => horizontal[(r, c)] = true,
+            }
         }
     }
-    let mut ncycles = 0;
-    let bar = ProgressBar::new((orig_grid.rows() * orig_grid.cols()) as u64);
-    for ((row, col), &val) in orig_grid.indexed_iter() {
-        bar.inc(1);
-        if val != '.' {
+    let mut drawn = grid.clone();
+    for ((r, c), ch) in drawn.indexed_iter_mut() {
+        if HEADINGS.contains(ch) {
             continue;
         }
+        *ch = match (vertical[(r, c)], horizontal[(r, c)]) {
+            (true, true) => '+',
+            (true, false) => '|',
+            (false, true) => '-',
+            (false, false) => *ch,
+        };
+    }
+    drawn[block] = 'O';
+    drawn
+}
 
-        let mut grid = orig_grid.clone();
-        let mut dir_visited = HashMap::<char, Grid<bool>>::new();
-        for dir in ['^', '>', 'v', '<'] {
-            let mut g = Grid::new(orig_grid.rows(), orig_grid.cols());
-            g.fill(false);
-            dir_visited.insert(dir, g);
-        }
-        let mut guard = orig_guard;
-        grid[(row, col)] = '#';
+// The guard whose patrol the extra obstacles are put in the way of.
+fn first_guard(grid: &Grid<char>) -> Result<Guard> {
+    find_guards(grid)
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no guard on the map"))
+}
 
-        let mut cycle = false;
-        while guard.is_some() && !cycle {
-            guard = step(&mut grid, guard);
-            if guard.is_none() {
-                break;
-            }
-            let pos = guard.unwrap();
-            let dir = grid[(pos.0, pos.1)];
-            if dir_visited[&dir][(pos.0, pos.1)] {
-                cycle = true;
-                break;
+// List every obstacle position which traps the guard, with the length of
+// the loop it causes, drawing the loop for `draw` if given.
+pub fn list_loops(inputfile: &str, rules: &GuardRules, draw: Option<(usize, usize)>) -> Result<()> {
+    let grid = read_grid(inputfile)?;
+    let lab = Lab::new(&grid, rules);
+    let guard = first_guard(&grid)?;
+    let start = lab.cell(&guard);
+    for block in lab.patrol_path(start, guard.dir).0 {
+        if block == start || lab.obstacles[block] {
+            continue;
+        }
+        if let Some(guard_loop) = lab.find_loop(start, guard.dir, Some(block)) {
+            let (r, c) = (block / lab.cols, block % lab.cols);
+            println!(
+                "obstacle at row {r} col {c}: loop of length {} entered at {:?}",
+                guard_loop.len(),
+                guard_loop.entry
+            );
+            if draw == Some((r, c)) {
+                _print_grid(&draw_loop(&grid, (r, c), &guard_loop));
             }
-            let dict = dir_visited.get_mut(&dir).unwrap();
-            dict[pos] = true;
         }
-        if cycle {
-            ncycles += 1
+    }
+    Ok(())
+}
+
+// Number of places an extra obstacle traps the first guard in a loop.
+fn count_loops(grid: &Grid<char>, rules: &GuardRules) -> Result<i32> {
+    let lab = Lab::new(grid, rules);
+    let guard = first_guard(grid)?;
+    let start = lab.cell(&guard);
+    let mut seen = vec![0u32; 4 * lab.rows * lab.cols];
+    let mut ncycles = 0;
+    // an obstacle off the original path can never be reached
+    for (stamp, block) in lab.patrol_path(start, guard.dir).0.into_iter().enumerate() {
+        if block != start
+            && !lab.obstacles[block]
+            && lab.loops(start, guard.dir, block, &mut seen, stamp as u32 + 1)
+        {
+            ncycles += 1;
         }
     }
-    bar.finish();
+    Ok(ncycles)
+}
+
+fn part2(inputfile: &str) -> Result<i32> {
+    let grid = read_grid(inputfile)?;
+    let ncycles = count_loops(&grid, &GuardRules::default())?;
     println!("{ncycles}");
     Ok(ncycles)
 }
@@ -155,6 +531,99 @@ fn test_part2() {
     assert_eq!(part2("./input/day6_test.txt").unwrap(), 6);
 }
 
+#[test]
+fn test_find_loop() {
+    let grid = read_grid("./input/day6_test.txt").unwrap();
+    let lab = Lab::new(&grid, &GuardRules::default());
+    let start = grid.iter().position(|&ch| ch == '^').unwrap();
+    assert!(lab.find_loop(start, 0, None).is_none());
+    let guard_loop = lab.find_loop(start, 0, Some(6 * lab.cols + 3)).unwrap();
+    assert_eq!(guard_loop.len(), 18);
+    assert_eq!(guard_loop.entry, (5, 4, '^'));
+    let drawn = draw_loop(&grid, (6, 3), &guard_loop);
+    let rows: Vec<String> = drawn.iter_rows().map(|row| row.collect()).collect();
+    assert_eq!(
+        rows,
+        vec![
+            "....#.....",
+            "....+---+#",
+            "....|...|.",
+            "..#.|...|.",
+            "....|..#|.",
+            "....|...|.",
+            ".#.O^---+.",
+            "........#.",
+            "#.........",
+            "......#...",
+        ]
+    );
+}
+
+#[test]
+fn test_guard_rules() {
+    let grid = read_grid("./input/day6_test.txt").unwrap();
+    let rules = GuardRules {
+        exit: Exit::Wrap,
+        ..GuardRules::default()
+    };
+    let (_, looped) = patrol(&grid, &rules);
+    assert_eq!(looped, vec![true]);
+
+    let mut grid = Grid::from_vec("#..#....^..^".chars().collect(), 4);
+    let count = |visited: Grid<bool>| visited.iter().filter(|&&v| v).count();
+    let rules = GuardRules::from_options(&["--turn".to_string(), "back".to_string()]).unwrap();
+    let (visited, looped) = patrol(&grid, &rules);
+    assert_eq!(looped, vec![false, false]);
+    assert_eq!(count(visited), 4);
+    let mut rules = GuardRules {
+        turn: Turn::Left,
+        ..GuardRules::default()
+    };
+    assert_eq!(count(patrol(&grid, &rules).0), 6);
+    grid[(1, 0)] = 'O';
+    rules.obstacles.push('O');
+    let (visited, looped) = patrol(&grid, &rules);
+    assert_eq!(looped, vec![false, false]);
+    assert!(visited[(2, 1)] && !visited[(1, 0)]);
+}
+
+#[test]
+fn test_lab_rules() {
+    let options = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let data = fs::read_to_string("./input/day6_test.txt").unwrap();
+    let cols = data.lines().next().unwrap().len();
+    let grid = Grid::from_vec(
+        data.replace('#', "X").replace('\n', "").chars().collect(),
+        cols,
+    );
+    let rules = GuardRules::from_options(&options("--loops --obstacles X")).unwrap();
+    assert_eq!(count_loops(&grid, &rules).unwrap(), 6);
+    // the jump tables agree with stepping a cell at a time
+    for turn in ["right", "left", "back"] {
+        for wrap in ["", " --wrap"] {
+            let rules = format!("--obstacles X --turn {turn}{wrap}");
+            let rules = GuardRules::from_options(&options(&rules)).unwrap();
+            let lab = Lab::new(&grid, &rules);
+            let guard = first_guard(&grid).unwrap();
+            let start = lab.cell(&guard);
+            let (path, _) = lab.patrol_path(start, guard.dir);
+            let stepped = path
+                .into_iter()
+                .filter(|&b| b != start && lab.find_loop(start, guard.dir, Some(b)).is_some())
+                .count();
+            assert_eq!(count_loops(&grid, &rules).unwrap(), stepped as i32);
+        }
+    }
+    // an empty row on a wrapped map goes round forever
+    let grid = Grid::from_vec("#..>".chars().collect(), 2);
+    let rules = GuardRules::from_options(&options("--wrap")).unwrap();
+    assert_eq!(patrol(&grid, &rules).1, vec![true]);
+    assert!(GuardRules::from_options(&options("--obstacles --wrap")).is_err());
+    assert!(GuardRules::from_options(&options("--turn")).is_err());
+    assert!(GuardRules::from_options(&options("--wrpa")).is_err());
+    assert!(GuardRules::from_options(&options("--loops 3,4 --patrol")).is_ok());
+}
+
 pub fn run(inputfile: &str) -> Result<(i32, i32)> {
     Ok((part1(inputfile)? as i32, part2(inputfile)?))
 }
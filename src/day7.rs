@@ -1,76 +1,722 @@
-use anyhow::Result;
-use itertools::{repeat_n, Itertools};
+use anyhow::{anyhow, Result};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs;
+use std::hash::Hash;
 use std::iter::zip;
+use std::str::FromStr;
 
-fn read_input(inputfile: &str) -> Result<(Vec<i64>, Vec<Vec<i64>>)> {
-    let data = fs::read_to_string(inputfile)?;
-    let mut totals: Vec<i64> = vec![];
-    let mut all_terms: Vec<Vec<i64>> = vec![];
+// The numbers an equation is made of: `i64` by default, or arbitrary
+// precision with the `bigint` feature. Operations give `None` instead of
+// overflowing or dividing by zero.
+trait Value: Clone + Eq + Hash + PartialOrd + Display + FromStr {
+    fn from_i64(n: i64) -> Self;
+    fn try_add(&self, b: &Self) -> Option<Self>;
+    fn try_sub(&self, b: &Self) -> Option<Self>;
+    fn try_mul(&self, b: &Self) -> Option<Self>;
+    // truncating, like `/` and `%` on integers
+    fn try_div(&self, b: &Self) -> Option<Self>;
+    fn try_rem(&self, b: &Self) -> Option<Self>;
+    fn try_pow(&self, exp: &Self) -> Option<Self>;
+    fn xor(&self, b: &Self) -> Self;
+    // number of decimal digits, ignoring any sign
+    fn digits(&self) -> u32;
+}
+
+impl Value for i64 {
+    fn from_i64(n: i64) -> i64 {
+        n
+    }
+    fn try_add(&self, b: &i64) -> Option<i64> {
+        self.checked_add(*b)
+    }
+    fn try_sub(&self, b: &i64) -> Option<i64> {
+        self.checked_sub(*b)
+    }
+    fn try_mul(&self, b: &i64) -> Option<i64> {
+        self.checked_mul(*b)
+    }
+    fn try_div(&self, b: &i64) -> Option<i64> {
+        self.checked_div(*b)
+    }
+    fn try_rem(&self, b: &i64) -> Option<i64> {
+        self.checked_rem(*b)
+    }
+    fn try_pow(&self, exp: &i64) -> Option<i64> {
+        self.checked_pow(u32::try_from(*exp).ok()?)
+    }
+    fn xor(&self, b: &i64) -> i64 {
+        self ^ b
+    }
+    fn digits(&self) -> u32 {
+        self.unsigned_abs().checked_ilog10().unwrap_or(0) + 1
+    }
+}
+
+// Refuse powers with more bits than this rather than run out of memory
+#[cfg(feature = "bigint")]
+const MAX_BITS: u64 = 1 << 20;
+
+#[cfg(feature = "bigint")]
+impl Value for BigInt {
+    fn from_i64(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+    fn try_add(&self, b: &BigInt) -> Option<BigInt> {
+        Some(self + b)
+    }
+    fn try_sub(&self, b: &BigInt) -> Option<BigInt> {
+        Some(self - b)
+    }
+    fn try_mul(&self, b: &BigInt) -> Option<BigInt> {
+        Some(self * b)
+    }
+    fn try_div(&self, b: &BigInt) -> Option<BigInt> {
+        (*b != BigInt::ZERO).then(|| self / b)
+    }
+    fn try_rem(&self, b: &BigInt) -> Option<BigInt> {
+        (*b != BigInt::ZERO).then(|| self % b)
+    }
+    fn try_pow(&self, exp: &BigInt) -> Option<BigInt> {
+        let exp = u32::try_from(exp).ok()?;
+        if self.bits().saturating_mul(exp as u64) > MAX_BITS {
+            return None;
+        }
+        Some(self.pow(exp))
+    }
+    fn xor(&self, b: &BigInt) -> BigInt {
+        self ^ b
+    }
+    fn digits(&self) -> u32 {
+        self.magnitude().to_string().len() as u32
+    }
+}
+
+fn read_input<V: Value>(inputfile: &str) -> Result<(Vec<V>, Vec<Vec<V>>)> {
+    read_input_str(&fs::read_to_string(inputfile)?)
+}
+
+fn read_input_str<V: Value>(data: &str) -> Result<(Vec<V>, Vec<Vec<V>>)> {
+    let parse = |field: &str| {
+        field
+            .parse::<V>()
+            .map_err(|_| anyhow!("can't parse {field} (too big? try --bigint)"))
+    };
+    let mut totals: Vec<V> = vec![];
+    let mut all_terms: Vec<Vec<V>> = vec![];
     for line in data.lines() {
         let mut fields = line.split_whitespace();
         let mut total = fields.next().unwrap().to_string();
         total.pop(); // remove the trailing colon
-        totals.push(total.parse().unwrap());
-        let mut v: Vec<i64> = vec![];
+        totals.push(parse(&total)?);
+        let mut v: Vec<V> = vec![];
         for term in fields {
-            v.push(term.parse().unwrap());
+            v.push(parse(term)?);
         }
         all_terms.push(v);
     }
     Ok((totals, all_terms))
 }
 
-fn check(total: i64, terms: &Vec<i64>, ops: &[char]) -> bool {
-    let n_op = terms.len() - 1;
-    let perms: Vec<_> = repeat_n(ops, n_op).multi_cartesian_product().collect();
-    for perm in perms {
-        let mut nums = terms.clone().into_iter();
-        let mut result = nums.next().unwrap();
-        for op in perm {
-            result = match op {
-                '+' => result + nums.next().unwrap(),
-                '*' => result * nums.next().unwrap(),
-                '|' => (result.to_string() + &nums.next().unwrap().to_string())
-                    .parse::<i64>()
-                    .unwrap(),
-                _ => panic!("bad operator"),
+// What `prefix` must have been for `prefix <op> last` to give `result`.
+enum Inverse<V> {
+    Prefix(V),
+    Impossible,
+    // every prefix works, as for multiplying by zero
+    Any,
+}
+
+trait Operator<V> {
+    // as written in an expression, e.g. `||`
+    fn symbol(&self) -> &'static str;
+    // higher binds tighter when evaluating with precedence
+    fn precedence(&self) -> u8;
+    fn right_associative(&self) -> bool {
+        false
+    }
+    // `None` on overflow or when the operation is undefined
+    fn apply(&self, a: &V, b: &V) -> Option<V>;
+    // whether `invert` can be used to work back from the total
+    fn invertible(&self) -> bool {
+        false
+    }
+    fn invert(&self, _result: &V, _b: &V) -> Inverse<V> {
+        Inverse::Impossible
+    }
+    // whether `a <op> b` is at least `a` for any non-negative `a`, and
+    // never smaller for bigger operands, so that a value which is already
+    // too big can't be brought back down
+    fn monotone(&self, _b: &V) -> bool {
+        false
+    }
+}
+
+fn prefix_or_impossible<V>(prefix: Option<V>) -> Inverse<V> {
+    prefix.map_or(Inverse::Impossible, Inverse::Prefix)
+}
+
+struct Add;
+struct Multiply;
+struct Concatenate;
+struct Subtract;
+struct Divide;
+struct Power;
+struct Xor;
+
+impl<V: Value> Operator<V> for Add {
+    fn symbol(&self) -> &'static str {
+        "+"
+    }
+    fn precedence(&self) -> u8 {
+        2
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        a.try_add(b)
+    }
+    fn invertible(&self) -> bool {
+        true
+    }
+    fn invert(&self, result: &V, b: &V) -> Inverse<V> {
+        prefix_or_impossible(result.try_sub(b))
+    }
+    fn monotone(&self, b: &V) -> bool {
+        *b >= V::from_i64(0)
+    }
+}
+
+impl<V: Value> Operator<V> for Multiply {
+    fn symbol(&self) -> &'static str {
+        "*"
+    }
+    fn precedence(&self) -> u8 {
+        3
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        a.try_mul(b)
+    }
+    fn invertible(&self) -> bool {
+        true
+    }
+    fn monotone(&self, b: &V) -> bool {
+        *b >= V::from_i64(1)
+    }
+    fn invert(&self, result: &V, b: &V) -> Inverse<V> {
+        let zero = V::from_i64(0);
+        if *b == zero {
+            return if *result == zero {
+                Inverse::Any
+            } else {
+                Inverse::Impossible
+            };
+        }
+        match result.try_rem(b) {
+            Some(rem) if rem == zero => prefix_or_impossible(result.try_div(b)),
+            _ => Inverse::Impossible,
+        }
+    }
+}
+
+// 10 to the power of the number of digits in `b`
+fn concat_shift<V: Value>(b: &V) -> Option<V> {
+    V::from_i64(10).try_pow(&V::from_i64(b.digits() as i64))
+}
+
+impl<V: Value> Operator<V> for Concatenate {
+    fn symbol(&self) -> &'static str {
+        "||"
+    }
+    fn precedence(&self) -> u8 {
+        1
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        let zero = V::from_i64(0);
+        if *a < zero || *b < zero {
+            return None;
+        }
+        a.try_mul(&concat_shift(b)?)?.try_add(b)
+    }
+    fn invertible(&self) -> bool {
+        true
+    }
+    fn monotone(&self, b: &V) -> bool {
+        *b >= V::from_i64(0)
+    }
+    fn invert(&self, result: &V, b: &V) -> Inverse<V> {
+        if *b < V::from_i64(0) || result < b {
+            return Inverse::Impossible;
+        }
+        let Some(shift) = concat_shift(b) else {
+            return Inverse::Impossible;
+        };
+        match result.try_rem(&shift) {
+            Some(rem) if rem == *b => prefix_or_impossible(result.try_div(&shift)),
+            _ => Inverse::Impossible,
+        }
+    }
+}
+
+impl<V: Value> Operator<V> for Subtract {
+    fn symbol(&self) -> &'static str {
+        "-"
+    }
+    fn precedence(&self) -> u8 {
+        2
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        a.try_sub(b)
+    }
+    fn invertible(&self) -> bool {
+        true
+    }
+    fn invert(&self, result: &V, b: &V) -> Inverse<V> {
+        prefix_or_impossible(result.try_add(b))
+    }
+}
+
+impl<V: Value> Operator<V> for Divide {
+    fn symbol(&self) -> &'static str {
+        "/"
+    }
+    fn precedence(&self) -> u8 {
+        3
+    }
+    // truncating division, so there is no single prefix to work back to
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        a.try_div(b)
+    }
+}
+
+impl<V: Value> Operator<V> for Power {
+    fn symbol(&self) -> &'static str {
+        "**"
+    }
+    fn precedence(&self) -> u8 {
+        4
+    }
+    fn right_associative(&self) -> bool {
+        true
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        a.try_pow(b)
+    }
+}
+
+impl<V: Value> Operator<V> for Xor {
+    fn symbol(&self) -> &'static str {
+        "^"
+    }
+    fn precedence(&self) -> u8 {
+        0
+    }
+    fn apply(&self, a: &V, b: &V) -> Option<V> {
+        Some(a.xor(b))
+    }
+    fn invertible(&self) -> bool {
+        true
+    }
+    fn invert(&self, result: &V, b: &V) -> Inverse<V> {
+        Inverse::Prefix(result.xor(b))
+    }
+}
+
+// Look up an operator by its symbol or name.
+fn operator<V: Value>(name: &str) -> Option<Box<dyn Operator<V>>> {
+    let op: Box<dyn Operator<V>> = match name {
+        "+" | "add" => Box::new(Add),
+        "*" | "mul" => Box::new(Multiply),
+        "||" | "concat" => Box::new(Concatenate),
+        "-" | "sub" => Box::new(Subtract),
+        "/" | "div" => Box::new(Divide),
+        "**" | "pow" => Box::new(Power),
+        "^" | "xor" => Box::new(Xor),
+        _ => return None,
+    };
+    Some(op)
+}
+
+// A partly evaluated expression: operands, and the operators between them
+// still waiting for what follows in case it binds more tightly.
+type Partial<V> = (Vec<V>, Vec<usize>);
+
+// Finds which sequences of `ops` make the terms of an equation evaluate to
+// its total. A sequence is a list of indices into `ops`.
+struct Solver<V> {
+    ops: Vec<Box<dyn Operator<V>>>,
+    precedence: bool,
+}
+
+impl<V: Value> Solver<V> {
+    // With `precedence` set, expressions are evaluated using each
+    // operator's precedence rather than strictly left to right.
+    fn new(names: &[&str], precedence: bool) -> Result<Solver<V>> {
+        let ops = names
+            .iter()
+            .map(|name| operator(name).ok_or_else(|| anyhow!("unknown operator {name}")))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Solver { ops, precedence })
+    }
+
+    // Equations can be worked backwards from the total, strictly left to
+    // right, when every operator can be undone.
+    fn backwards(&self) -> bool {
+        !self.precedence && self.ops.iter().all(|op| op.invertible())
+    }
+
+    // Whether no operator can make the value of `terms` smaller as more of
+    // them are added, so a prefix which is already too big can be dropped.
+    fn monotone(&self, terms: &[V]) -> bool {
+        let zero = V::from_i64(0);
+        terms.iter().all(|term| *term >= zero)
+            && terms[1..]
+                .iter()
+                .all(|term| self.ops.iter().all(|op| op.monotone(term)))
+    }
+
+    // Add `<op> term` to a partly evaluated expression, first reducing the
+    // pending operators which bind at least as tightly (shunting-yard), or
+    // all of them when evaluating strictly left to right.
+    fn push(&self, partial: &Partial<V>, op: usize, term: &V) -> Option<Partial<V>> {
+        let (mut values, mut pending) = partial.clone();
+        while let Some(&top) = pending.last() {
+            let (p_top, p_op) = (self.ops[top].precedence(), self.ops[op].precedence());
+            let tighter = p_top > p_op || (p_top == p_op && !self.ops[op].right_associative());
+            if self.precedence && !tighter {
+                break;
             }
+            let b = values.pop()?;
+            let a = values.pop()?;
+            values.push(self.ops[top].apply(&a, &b)?);
+            pending.pop();
         }
-        if result == total {
+        pending.push(op);
+        values.push(term.clone());
+        Some((values, pending))
+    }
+
+    // The value of a partly evaluated expression as it stands.
+    fn finish(&self, partial: &Partial<V>) -> Option<V> {
+        let (mut values, mut pending) = partial.clone();
+        while let Some(top) = pending.pop() {
+            let b = values.pop()?;
+            let a = values.pop()?;
+            values.push(self.ops[top].apply(&a, &b)?);
+        }
+        values.pop()
+    }
+
+    #[cfg(test)]
+    fn evaluate(&self, terms: &[V], seq: &[usize]) -> Option<V> {
+        let start = (vec![terms[0].clone()], vec![]);
+        let partial = zip(seq, &terms[1..])
+            .try_fold(start, |partial, (&op, term)| self.push(&partial, op, term))?;
+        self.finish(&partial)
+    }
+
+    // The number of operator sequences which make `terms` evaluate to `total`.
+    fn count(&self, total: &V, terms: &[V]) -> u64 {
+        if self.backwards() {
+            self.count_backwards(total, terms)
+        } else if self.monotone(terms) {
+            let ways = self.count_values(terms, total);
+            ways.get(total).copied().unwrap_or(0)
+        } else {
+            self.count_forwards(terms, Some(total))
+        }
+    }
+
+    fn count_backwards(&self, total: &V, terms: &[V]) -> u64 {
+        match terms {
+            [] => 0,
+            [first] => (first == total) as u64,
+            [rest @ .., last] => self
+                .ops
+                .iter()
+                .map(|op| match op.invert(total, last) {
+                    Inverse::Prefix(prefix) => self.count_backwards(&prefix, rest),
+                    Inverse::Any => self.count_forwards(rest, None),
+                    Inverse::Impossible => 0,
+                })
+                .fold(0, u64::saturating_add),
+        }
+    }
+
+    // The number of sequences found by `search`, with no more memory than
+    // the sequence being tried.
+    fn count_forwards(&self, terms: &[V], target: Option<&V>) -> u64 {
+        let mut count = 0u64;
+        self.search(terms, target, None, &mut |_| {
+            count = count.saturating_add(1);
+            true
+        });
+        count
+    }
+
+    // The number of operator sequences giving each value of `terms` up to
+    // `bound`, for operators which never make a value smaller. Sequences
+    // which reach the same partly evaluated expression are counted together
+    // rather than tried one by one, and those that overflow or go past the
+    // bound are dropped.
+    fn count_values(&self, terms: &[V], bound: &V) -> HashMap<V, u64> {
+        let mut values: HashMap<V, u64> = HashMap::new();
+        let Some((first, rest)) = terms.split_first() else {
+            return values;
+        };
+        let mut ways: HashMap<Partial<V>, u64> =
+            HashMap::from([((vec![first.clone()], vec![]), 1)]);
+        for term in rest {
+            let mut next: HashMap<Partial<V>, u64> = HashMap::new();
+            for (partial, count) in &ways {
+                for op in 0..self.ops.len() {
+                    let Some(pushed) = self.push(partial, op, term) else {
+                        continue;
+                    };
+                    if self.finish(&pushed).is_none_or(|value| value > *bound) {
+                        continue;
+                    }
+                    let entry = next.entry(pushed).or_default();
+                    *entry = entry.saturating_add(*count);
+                }
+            }
+            ways = next;
+        }
+        for (partial, count) in ways {
+            if let Some(value) = self.finish(&partial) {
+                let entry = values.entry(value).or_default();
+                *entry = entry.saturating_add(count);
+            }
+        }
+        values
+    }
+
+    // Up to `limit` operator sequences which make `terms` evaluate to `total`.
+    fn solutions(&self, total: &V, terms: &[V], limit: usize) -> Vec<Vec<usize>> {
+        let mut found = vec![];
+        if terms.is_empty() || limit == 0 {
+            return found;
+        }
+        if self.backwards() {
+            self.collect_backwards(total, terms, limit, &mut vec![], &mut found);
+        } else {
+            let bound = self.monotone(terms).then_some(total);
+            self.search(terms, Some(total), bound, &mut |seq| {
+                found.push(seq.to_vec());
+                found.len() < limit
+            });
+        }
+        found
+    }
+
+    // Try operators one at a time from the left, passing each sequence
+    // which makes `terms` evaluate to `target` (or evaluate at all, for
+    // `None`) to `visit` until it returns false. Sequences which overflow
+    // are skipped, and so is everything after a prefix past `bound`.
+    fn search(
+        &self,
+        terms: &[V],
+        target: Option<&V>,
+        bound: Option<&V>,
+        visit: &mut dyn FnMut(&[usize]) -> bool,
+    ) {
+        let Some(first) = terms.first() else {
+            return;
+        };
+        let start = (vec![first.clone()], vec![]);
+        self.search_from(terms, target, bound, &start, &mut vec![], visit);
+    }
+
+    // Returns false once `visit` has asked to stop.
+    fn search_from(
+        &self,
+        terms: &[V],
+        target: Option<&V>,
+        bound: Option<&V>,
+        partial: &Partial<V>,
+        seq: &mut Vec<usize>,
+        visit: &mut dyn FnMut(&[usize]) -> bool,
+    ) -> bool {
+        let placed = seq.len() + 1;
+        if placed == terms.len() {
+            let value = self.finish(partial);
+            if value.is_some() && target.is_none_or(|target| value.as_ref() == Some(target)) {
+                return visit(seq);
+            }
             return true;
         }
-        // println!("{}", result)
+        for op in 0..self.ops.len() {
+            let Some(pushed) = self.push(partial, op, &terms[placed]) else {
+                continue;
+            };
+            if bound.is_some_and(|bound| self.finish(&pushed).is_none_or(|v| v > *bound)) {
+                continue;
+            }
+            seq.push(op);
+            let go_on = self.search_from(terms, target, bound, &pushed, seq, visit);
+            seq.pop();
+            if !go_on {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Work backwards from the total, peeling off the last term with the
+    // inverse of each operator so impossible branches are pruned early.
+    // `suffix` holds the operators already peeled off, last one first.
+    fn collect_backwards(
+        &self,
+        total: &V,
+        terms: &[V],
+        limit: usize,
+        suffix: &mut Vec<usize>,
+        found: &mut Vec<Vec<usize>>,
+    ) {
+        match terms {
+            [] => (),
+            [first] => {
+                if first == total && found.len() < limit {
+                    found.push(suffix.iter().rev().copied().collect());
+                }
+            }
+            [rest @ .., last] => {
+                for (idx, op) in self.ops.iter().enumerate() {
+                    if found.len() >= limit {
+                        return;
+                    }
+                    match op.invert(total, last) {
+                        Inverse::Prefix(prefix) => {
+                            suffix.push(idx);
+                            self.collect_backwards(&prefix, rest, limit, suffix, found);
+                            suffix.pop();
+                        }
+                        Inverse::Any => {
+                            // every choice of operators before this one works,
+                            // as long as it doesn't overflow
+                            let mut prefixes = vec![];
+                            let limit = limit - found.len();
+                            self.search(rest, None, None, &mut |seq| {
+                                prefixes.push(seq.to_vec());
+                                prefixes.len() < limit
+                            });
+                            for mut seq in prefixes {
+                                seq.push(idx);
+                                seq.extend(suffix.iter().rev());
+                                found.push(seq);
+                            }
+                        }
+                        Inverse::Impossible => (),
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&self, total: &V, terms: &[V]) -> bool {
+        if self.backwards() || !self.monotone(terms) {
+            !self.solutions(total, terms, 1).is_empty()
+        } else {
+            self.count(total, terms) > 0
+        }
+    }
+
+    // Write out an equation as in the puzzle, e.g. `81 + 40 * 27`.
+    fn format_expr(&self, terms: &[V], seq: &[usize]) -> String {
+        let mut expr = terms[0].to_string();
+        for (&op, term) in zip(seq, &terms[1..]) {
+            expr += &format!(" {} {term}", self.ops[op].symbol());
+        }
+        expr
     }
-    false
 }
 
-fn part1(inputfile: &str) -> Result<i64> {
-    let (totals, all_terms) = read_input(inputfile)?;
-    let mut sum_valid = 0;
+// Print how each equation can be satisfied: the number of ways and either
+// the first expression found or, with `all`, every one of them.
+fn audit<V: Value>(inputfile: &str, solver: &Solver<V>, all: bool) -> Result<()> {
+    let (totals, all_terms) = read_input::<V>(inputfile)?;
     for (total, terms) in zip(totals, all_terms) {
-        let valid = check(total, &terms, &['+', '*']);
-        if valid {
-            sum_valid += total
+        let count = solver.count(&total, &terms);
+        let limit = if all { usize::MAX } else { 1 };
+        let found = if count > 0 {
+            solver.solutions(&total, &terms, limit)
+        } else {
+            vec![]
+        };
+        match found.first() {
+            Some(seq) => println!(
+                "{total} = {} ({count} ways)",
+                solver.format_expr(&terms, seq)
+            ),
+            None => println!("{total}: no solution"),
+        }
+        for seq in found.iter().skip(1) {
+            println!("{total} = {}", solver.format_expr(&terms, seq));
         }
-        // println!("{} {:?} {}", total, terms, valid);
     }
-    Ok(sum_valid)
+    Ok(())
 }
 
-fn part2(inputfile: &str) -> Result<i64> {
-    let (totals, all_terms) = read_input(inputfile)?;
-    let mut sum_valid = 0;
+fn sum_valid<V: Value>(inputfile: &str, solver: &Solver<V>) -> Result<V> {
+    sum_valid_str(&fs::read_to_string(inputfile)?, solver)
+}
+
+fn sum_valid_str<V: Value>(data: &str, solver: &Solver<V>) -> Result<V> {
+    let (totals, all_terms) = read_input_str::<V>(data)?;
+    let mut sum_valid = V::from_i64(0);
     for (total, terms) in zip(totals, all_terms) {
-        let valid = check(total, &terms, &['+', '*', '|']);
+        let valid = solver.check(&total, &terms);
         if valid {
-            sum_valid += total
+            sum_valid = sum_valid
+                .try_add(&total)
+                .ok_or_else(|| anyhow!("sum of valid totals overflows i64, try --bigint"))?;
         }
         // println!("{} {:?} {}", total, terms, valid);
     }
     Ok(sum_valid)
 }
 
+fn report<V: Value>(
+    inputfile: &str,
+    names: &[&str],
+    precedence: bool,
+    audit_all: Option<bool>,
+) -> Result<()> {
+    let solver = Solver::<V>::new(names, precedence)?;
+    if let Some(all) = audit_all {
+        audit(inputfile, &solver, all)?;
+    }
+    let total = sum_valid(inputfile, &solver)?;
+    println!("Calibration total with {:?}: {total}", names);
+    Ok(())
+}
+
+// Print the calibration total using the operators in `names`, after
+// auditing each equation if `audit_all` is given. With `bigint` the sums
+// are done with arbitrary precision instead of `i64`.
+pub fn calibrate(
+    inputfile: &str,
+    names: &[&str],
+    precedence: bool,
+    bigint: bool,
+    audit_all: Option<bool>,
+) -> Result<()> {
+    if bigint {
+        #[cfg(feature = "bigint")]
+        return report::<BigInt>(inputfile, names, precedence, audit_all);
+        #[cfg(not(feature = "bigint"))]
+        return Err(anyhow!("--bigint needs a build with --features bigint"));
+    }
+    report::<i64>(inputfile, names, precedence, audit_all)
+}
+
+fn part1(inputfile: &str) -> Result<i64> {
+    sum_valid(inputfile, &Solver::new(&["+", "*"], false)?)
+}
+
+fn part2(inputfile: &str) -> Result<i64> {
+    sum_valid(inputfile, &Solver::new(&["+", "*", "||"], false)?)
+}
+
 #[test]
 fn test_part1() {
     assert_eq!(part1("./input/day7_test.txt").unwrap(), 3749);
@@ -81,6 +727,131 @@ fn test_part2() {
     assert_eq!(part2("./input/day7_test.txt").unwrap(), 11387);
 }
 
+#[test]
+fn test_check() {
+    let solver = Solver::<i64>::new(&["+", "*"], false).unwrap();
+    assert!(solver.check(&3267, &[81, 40, 27]));
+    assert!(!solver.check(&7290, &[6, 8, 6, 15]));
+    assert!(solver.check(&0, &[5, 3, 0]));
+    let solver = Solver::<i64>::new(&["+", "*", "||"], false).unwrap();
+    assert!(solver.check(&7290, &[6, 8, 6, 15]));
+    // would overflow i64 if evaluated forwards
+    assert!(!solver.check(&10, &[i64::MAX, 2, 3]));
+    let solver = Solver::<i64>::new(&["||"], false).unwrap();
+    assert!(solver.check(&100, &[10, 0]));
+}
+
+#[test]
+fn test_solutions() {
+    let solver = Solver::<i64>::new(&["+", "*"], false).unwrap();
+    let exprs: Vec<String> = solver
+        .solutions(&3267, &[81, 40, 27], usize::MAX)
+        .iter()
+        .map(|seq| solver.format_expr(&[81, 40, 27], seq))
+        .collect();
+    assert_eq!(exprs, vec!["81 * 40 + 27", "81 + 40 * 27"]);
+    assert_eq!(solver.count(&3267, &[81, 40, 27]), 2);
+    let solver = Solver::<i64>::new(&["+", "*", "||"], false).unwrap();
+    let seq = &solver.solutions(&7290, &[6, 8, 6, 15], 1)[0];
+    assert_eq!(solver.format_expr(&[6, 8, 6, 15], seq), "6 * 8 || 6 * 15");
+    // any operator between 1 and 2 works before multiplying by zero
+    assert_eq!(solver.count(&0, &[1, 2, 0]), 3);
+    assert_eq!(solver.solutions(&0, &[1, 2, 0], usize::MAX).len(), 3);
+    assert_eq!(solver.count(&190, &[10, 19]), 1);
+    assert!(solver.solutions(&83, &[17, 5], usize::MAX).is_empty());
+}
+
+#[test]
+fn test_operators() {
+    assert!(Solver::<i64>::new(&["+", "%"], false).is_err());
+    let solver = Solver::<i64>::new(&["-", "/", "**", "^"], false).unwrap();
+    assert!(!solver.backwards());
+    let exprs: Vec<String> = solver
+        .solutions(&2, &[2, 3, 2, 3], usize::MAX)
+        .iter()
+        .map(|seq| solver.format_expr(&[2, 3, 2, 3], seq))
+        .collect();
+    assert_eq!(
+        exprs,
+        vec!["2 - 3 ** 2 ^ 3", "2 ** 3 - 2 / 3", "2 ^ 3 ** 2 ^ 3"]
+    );
+    // 5 ^ 3 == 6
+    assert!(solver.check(&6, &[5, 3]));
+    let solver = Solver::<i64>::new(&["+", "*", "**"], true).unwrap();
+    // 2 + 3 * 2 ** 2
+    assert_eq!(solver.evaluate(&[2, 3, 2, 2], &[0, 1, 2]), Some(14));
+    // 2 ** 3 ** 2 is right associative
+    assert_eq!(solver.evaluate(&[2, 3, 2], &[2, 2]), Some(512));
+    assert!(solver.check(&14, &[2, 3, 2, 2]));
+    let left_to_right = Solver::<i64>::new(&["+", "*", "**"], false).unwrap();
+    assert_eq!(left_to_right.evaluate(&[2, 3, 2, 2], &[0, 1, 2]), Some(100));
+}
+
+#[test]
+fn test_modes_agree() {
+    // every sequence tried one by one, skipping those that overflow
+    fn brute_force(solver: &Solver<i64>, total: i64, terms: &[i64]) -> u64 {
+        let n = solver.ops.len().pow(terms.len() as u32 - 1);
+        let sequences = (0..n).map(|mut idx| {
+            (1..terms.len())
+                .map(|_| {
+                    let op = idx % solver.ops.len();
+                    idx /= solver.ops.len();
+                    op
+                })
+                .collect::<Vec<_>>()
+        });
+        sequences
+            .filter(|seq| solver.evaluate(terms, seq) == Some(total))
+            .count() as u64
+    }
+    let (totals, all_terms) = read_input::<i64>("./input/day7_test.txt").unwrap();
+    let all = ["+", "*", "||", "-", "/", "**", "^"];
+    for (names, precedence) in [(&all[..3], false), (&all[..], false), (&all[..], true)] {
+        let solver = Solver::<i64>::new(names, precedence).unwrap();
+        for (total, terms) in zip(&totals, &all_terms) {
+            let count = solver.count(total, terms);
+            assert_eq!(count, brute_force(&solver, *total, terms));
+            assert_eq!(
+                solver.solutions(total, terms, usize::MAX).len() as u64,
+                count
+            );
+        }
+    }
+    // multiplying by zero works backwards from anything, but i64::MAX + 2
+    // and i64::MAX * 2 overflow going forwards
+    let solver = Solver::<i64>::new(&["+", "*"], false).unwrap();
+    assert!(solver.backwards());
+    assert_eq!(solver.count(&0, &[i64::MAX, 2, 0]), 0);
+    assert!(solver
+        .solutions(&0, &[i64::MAX, 2, 0], usize::MAX)
+        .is_empty());
+    assert_eq!(solver.count(&0, &[i64::MAX, 0, 0]), 3);
+    assert_eq!(
+        solver.count(&0, &[i64::MAX, 0, 0]),
+        brute_force(&solver, 0, &[i64::MAX, 0, 0])
+    );
+}
+
+#[test]
+fn test_too_big() {
+    // the total has 24 digits, too many for i64
+    let line = "617283945064938271605490: 123456789012 987654321098 5\n";
+    assert!(sum_valid_str(line, &Solver::<i64>::new(&["+", "*", "||"], false).unwrap()).is_err());
+    #[cfg(feature = "bigint")]
+    {
+        let solver = Solver::<BigInt>::new(&["+", "*", "||"], false).unwrap();
+        let total = sum_valid_str(line, &solver).unwrap();
+        assert_eq!(total.to_string(), "617283945064938271605490");
+        let (totals, all_terms) = read_input_str::<BigInt>(line).unwrap();
+        let seq = &solver.solutions(&totals[0], &all_terms[0], 1)[0];
+        assert_eq!(
+            solver.format_expr(&all_terms[0], seq),
+            "123456789012 || 987654321098 * 5"
+        );
+    }
+}
+
 pub fn run(inputfile: &str) -> Result<(i64, i64)> {
     Ok((part1(inputfile)?, part2(inputfile)?))
 }
@@ -1,14 +1,17 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use grid::Grid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::iter::zip;
 
-fn read_input(inputfile: &str) -> Result<Grid<char>> {
-    let data = fs::read_to_string(inputfile)?;
+fn parse_grid(data: &str) -> Grid<char> {
     let chars: Vec<_> = data.chars().filter(|c| *c != '\n').collect();
     let cols = data.lines().next().unwrap().len();
-    Ok(Grid::from_vec(chars, cols))
+    Grid::from_vec(chars, cols)
+}
+
+fn read_input(inputfile: &str) -> Result<Grid<char>> {
+    let data = fs::read_to_string(inputfile)?;
+    Ok(parse_grid(&data))
 }
 
 fn print_grid(grid: &Grid<char>) {
@@ -37,68 +40,379 @@ fn find_stations(grid: &Grid<char>) -> HashMap<char, Vec<(i32, i32)>> {
     stations
 }
 
-fn find_antinodes(
-    grid: &mut Grid<char>,
-    stations: &HashMap<char, Vec<(i32, i32)>>,
-    part2: bool,
-) -> Vec<(i32, i32)> {
-    let mut anti: Vec<(i32, i32)> = vec![];
-    for (station, coords) in stations.iter() {
-        println!("{:?}", station);
-        for i1 in 0..coords.len() {
-            for i2 in 0..coords.len() {
-                if i1 == i2 {
-                    continue;
+// A position on the grid is (row, col, 0).
+type Point = [i32; 3];
+
+// Which points in line with a pair of antennas are antinodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    // points beyond either antenna where the further one is `p / q` times
+    // as far away as the nearer one, `Ratio(2, 1)` in part 1
+    Ratio(i32, i32),
+    // every point in line with the pair, stepping by the distance between
+    // them or, with `reduce`, by that distance divided by its gcd so that
+    // no grid point is skipped
+    Harmonics { reduce: bool },
+}
+
+#[derive(Debug)]
+pub struct Resonance {
+    pub rule: Rule,
+    // ignore antinodes further than this from the nearer antenna
+    pub max_range: Option<i32>,
+}
+
+impl Default for Resonance {
+    fn default() -> Resonance {
+        Resonance {
+            rule: Rule::Ratio(2, 1),
+            max_range: None,
+        }
+    }
+}
+
+impl Resonance {
+    fn harmonics(reduce: bool) -> Resonance {
+        Resonance {
+            rule: Rule::Harmonics { reduce },
+            max_range: None,
+        }
+    }
+
+    // Parse `--ratio P:Q`, `--harmonics`, `--reduce` and `--range R`,
+    // passing over the other day 8 options.
+    pub fn from_options(options: &[String]) -> Result<Resonance> {
+        let mut rules = Resonance::default();
+        let mut options = options.iter().peekable();
+        while let Some(option) = options.next() {
+            let mut value = || {
+                options
+                    .next_if(|value| !value.starts_with("--"))
+                    .ok_or_else(|| anyhow!("{option} needs a value"))
+            };
+            let number = |text: &str| {
+                text.parse::<i32>()
+                    .map_err(|_| anyhow!("{option} needs whole numbers, not {text:?}"))
+            };
+            match option.as_str() {
+                "--ratio" => {
+                    let ratio = value()?;
+                    let (p, q) = ratio
+                        .split_once(':')
+                        .ok_or_else(|| anyhow!("ratio {ratio} is not P:Q"))?;
+                    let (p, q) = (number(p)?, number(q)?);
+                    if q <= 0 || p <= q {
+                        return Err(anyhow!("ratio {ratio} needs P > Q > 0"));
+                    }
+                    rules.rule = Rule::Ratio(p, q);
                 }
-                let d = (coords[i1].0 - coords[i2].0, coords[i1].1 - coords[i2].1);
+                "--harmonics" => rules.rule = Rule::Harmonics { reduce: false },
+                "--reduce" => rules.rule = Rule::Harmonics { reduce: true },
+                "--range" => rules.max_range = Some(number(value()?)?),
+                "--collinear" | "--overlay" => (),
+                "--points" => {
+                    value()?;
+                }
+                _ => return Err(anyhow!("unknown option {option}")),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn overflow() -> anyhow::Error {
+    anyhow!("antinode coordinates overflow i32")
+}
 
-                let mut nrange = 1..=1;
-                if part2 {
-                    nrange = 0..=100; // over-estimate
+fn offset(a: Point, d: Point, n: i32) -> Result<Point> {
+    let mut p = a;
+    for k in 0..3 {
+        p[k] = n
+            .checked_mul(d[k])
+            .and_then(|step| a[k].checked_add(step))
+            .ok_or_else(overflow)?;
+    }
+    Ok(p)
+}
+
+fn distance2(a: Point, b: Point) -> i64 {
+    (0..3).map(|k| (a[k] as i64 - b[k] as i64).pow(2)).sum()
+}
+
+// Antinodes of each frequency within the inclusive `bounds`, if any. Unless
+// `rules` limits the range, harmonics need bounds to stop somewhere.
+fn resonate(
+    stations: &HashMap<char, Vec<Point>>,
+    bounds: Option<(Point, Point)>,
+    rules: &Resonance,
+) -> Result<HashMap<char, HashSet<Point>>> {
+    if bounds.is_none() && rules.max_range.is_none() && matches!(rules.rule, Rule::Harmonics { .. })
+    {
+        return Err(anyhow!("harmonics without bounds need a maximum range"));
+    }
+    let in_bounds =
+        |p: Point| bounds.is_none_or(|(lo, hi)| (0..3).all(|k| lo[k] <= p[k] && p[k] <= hi[k]));
+    let mut antinodes = HashMap::new();
+    for (&station, coords) in stations.iter() {
+        let anti: &mut HashSet<Point> = antinodes.entry(station).or_default();
+        for i1 in 0..coords.len() {
+            for i2 in i1 + 1..coords.len() {
+                let (c1, c2) = (coords[i1], coords[i2]);
+                if c1 == c2 {
+                    continue;
                 }
-                for n in nrange {
-                    for (i, s) in zip([i1, i2], [1, -1]) {
-                        let a = (coords[i].0 + n * s * d.0, coords[i].1 + n * s * d.1);
-                        let r = grid.get_mut(a.0 as usize, a.1 as usize);
-                        if r.is_some() {
-                            *(r.unwrap()) = '#';
-                            if !anti.contains(&a) {
-                                anti.push(a);
+                let in_range = |p: Point| {
+                    rules
+                        .max_range
+                        .is_none_or(|r| distance2(p, c1).min(distance2(p, c2)) <= (r as i64).pow(2))
+                };
+                // from c2 towards c1
+                let d = offset(c1, c2, -1)?;
+                match rules.rule {
+                    Rule::Ratio(p, q) => {
+                        // beyond the nearer antenna by q / (p - q) of their separation
+                        for (near, dir) in [(c1, 1), (c2, -1)] {
+                            let scaled = offset([0; 3], d, q)?;
+                            if scaled.iter().all(|x| x % (p - q) == 0) {
+                                let step = scaled.map(|x| x / (p - q));
+                                let a = offset(near, step, dir)?;
+                                if in_bounds(a) && in_range(a) {
+                                    anti.insert(a);
+                                }
+                            }
+                        }
+                    }
+                    Rule::Harmonics { reduce } => {
+                        let mut step = d;
+                        let mut between = 1;
+                        if reduce {
+                            let g = gcd(gcd(d[0], d[1]), d[2]);
+                            step = d.map(|x| x / g);
+                            between = g;
+                        }
+                        // outwards from each antenna, then the points between them
+                        for (start, dir) in [(c1, 1), (c2, -1)] {
+                            let mut a = start;
+                            while in_bounds(a) && in_range(a) {
+                                anti.insert(a);
+                                a = offset(a, step, dir)?;
+                            }
+                        }
+                        for n in 1..between {
+                            let a = offset(c2, step, n)?;
+                            if in_bounds(a) && in_range(a) {
+                                anti.insert(a);
                             }
-                            // nanti = nanti + 1
                         }
                     }
                 }
+            }
+        }
+    }
+    Ok(antinodes)
+}
 
-                // println!("{} {:?} {} {:?} d {:?}", i1, coords[i1], i2, coords[i2], d);
+// Antinode positions for each antenna frequency
+type Antinodes = HashMap<char, HashSet<(i32, i32)>>;
+
+fn find_antinodes(
+    grid: &Grid<char>,
+    stations: &HashMap<char, Vec<(i32, i32)>>,
+    rules: &Resonance,
+) -> Antinodes {
+    let stations = stations
+        .iter()
+        .map(|(&station, coords)| (station, coords.iter().map(|&(i, j)| [i, j, 0]).collect()))
+        .collect();
+    let bounds = (
+        [0, 0, 0],
+        [grid.rows() as i32 - 1, grid.cols() as i32 - 1, 0],
+    );
+    resonate(&stations, Some(bounds), rules)
+        .unwrap()
+        .into_iter()
+        .map(|(station, anti)| (station, anti.into_iter().map(|p| (p[0], p[1])).collect()))
+        .collect()
+}
+
+// The frequencies with an antinode at each position, in order.
+fn owners(antinodes: &Antinodes) -> HashMap<(i32, i32), Vec<char>> {
+    let mut owners = HashMap::<(i32, i32), Vec<char>>::new();
+    for (&station, anti) in antinodes {
+        for &pos in anti {
+            owners.entry(pos).or_default().push(station);
+        }
+    }
+    for stations in owners.values_mut() {
+        stations.sort();
+    }
+    owners
+}
+
+fn unique_positions(antinodes: &Antinodes) -> usize {
+    owners(antinodes).len()
+}
+
+// How many positions are antinodes for 2, 3, ... frequencies at once.
+fn shared_counts(antinodes: &Antinodes) -> BTreeMap<usize, usize> {
+    let mut counts = BTreeMap::new();
+    for stations in owners(antinodes).values() {
+        if stations.len() > 1 {
+            *counts.entry(stations.len()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+// 256-colour palette codes, cycled through the frequencies in order
+const PALETTE: [u8; 12] = [196, 46, 33, 226, 201, 51, 208, 118, 129, 214, 39, 160];
+// for positions shared by several frequencies
+const SHARED: &str = "\x1b[1;7m";
+const RESET: &str = "\x1b[0m";
+
+fn colour(antinodes: &Antinodes) -> HashMap<char, String> {
+    let mut stations: Vec<char> = antinodes.keys().copied().collect();
+    stations.sort();
+    stations
+        .into_iter()
+        .enumerate()
+        .map(|(idx, station)| {
+            let code = PALETTE[idx % PALETTE.len()];
+            (station, format!("\x1b[38;5;{code}m"))
+        })
+        .collect()
+}
+
+// Draw the grid with each antinode coloured by its frequency, as '#' or
+// over the antenna already there.
+fn render(grid: &Grid<char>, antinodes: &Antinodes) -> String {
+    let owners = owners(antinodes);
+    let colour = colour(antinodes);
+    let mut out = String::new();
+    for rowidx in 0..grid.rows() {
+        for (colidx, &ch) in grid.iter_row(rowidx).enumerate() {
+            let shown = if ch == '.' { '#' } else { ch };
+            match owners.get(&(rowidx as i32, colidx as i32)) {
+                None => out.push(ch),
+                Some(stations) if stations.len() == 1 => {
+                    out.push_str(&format!("{}{shown}{RESET}", colour[&stations[0]]))
+                }
+                Some(_) => out.push_str(&format!("{SHARED}{shown}{RESET}")),
             }
         }
+        out.push('\n');
+    }
+    out
+}
+
+// Print the antinodes overlaid on the grid, followed by the number for
+// each frequency and how many positions several frequencies share.
+pub fn breakdown(inputfile: &str, rules: &Resonance) -> Result<()> {
+    let grid = read_input(inputfile)?;
+    let stations = find_stations(&grid);
+    let antinodes = find_antinodes(&grid, &stations, rules);
+    print!("{}", render(&grid, &antinodes));
+    let colour = colour(&antinodes);
+    let mut keys: Vec<&char> = antinodes.keys().collect();
+    keys.sort();
+    for station in keys {
+        println!(
+            "{}{station}{RESET}: {} antinodes",
+            colour[station],
+            antinodes[station].len()
+        );
     }
-    anti
+    println!("{} distinct positions", unique_positions(&antinodes));
+    for (n, count) in shared_counts(&antinodes) {
+        println!("{SHARED}{count}{RESET} positions shared by {n} frequencies");
+    }
+    Ok(())
 }
 
 fn part1(inputfile: &str) -> Result<i32> {
-    let mut grid = read_input(inputfile)?;
+    let grid = read_input(inputfile)?;
     // print_grid(&grid);
     let stations = find_stations(&grid);
     // println!("{:?}", stations);
     print_grid(&grid);
-    let anti = find_antinodes(&mut grid, &stations, false);
-    print_grid(&grid);
-    println!("{}", grid.iter().filter(|&c| *c == '#').count());
-    Ok(anti.len() as i32)
+    let antinodes = find_antinodes(&grid, &stations, &Resonance::default());
+    Ok(unique_positions(&antinodes) as i32)
 }
 
 fn part2(inputfile: &str) -> Result<i32> {
-    let mut grid = read_input(inputfile)?;
+    let grid = read_input(inputfile)?;
     // print_grid(&grid);
     let stations = find_stations(&grid);
     // println!("{:?}", stations);
     print_grid(&grid);
-    let anti = find_antinodes(&mut grid, &stations, true);
-    print_grid(&grid);
-    println!("{}", grid.iter().filter(|&c| *c == '#').count());
-    Ok(anti.len() as i32)
+    let antinodes = find_antinodes(&grid, &stations, &Resonance::harmonics(false));
+    Ok(unique_positions(&antinodes) as i32)
+}
+
+// The number of antinodes at every grid point in line with a pair of
+// antennas, rather than only at multiples of their separation.
+pub fn collinear(inputfile: &str) -> Result<usize> {
+    let grid = read_input(inputfile)?;
+    let stations = find_stations(&grid);
+    let antinodes = find_antinodes(&grid, &stations, &Resonance::harmonics(true));
+    Ok(unique_positions(&antinodes))
+}
+
+// Antennas listed one per line as a frequency followed by two or three
+// coordinates, e.g. `a 1,2,3`, with `#` starting a comment.
+fn parse_points(data: &str) -> Result<HashMap<char, Vec<Point>>> {
+    let mut stations = HashMap::<char, Vec<Point>>::new();
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(|c: char| c == ',' || c.is_whitespace());
+        let station = fields.next().unwrap();
+        let mut chars = station.chars();
+        let (Some(station), None) = (chars.next(), chars.next()) else {
+            return Err(anyhow!(
+                "line {}: frequency {station} is not one character",
+                idx + 1
+            ));
+        };
+        let coords = fields
+            .filter(|f| !f.is_empty())
+            .map(|f| f.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let point = match coords[..] {
+            [x, y] => [x, y, 0],
+            [x, y, z] => [x, y, z],
+            _ => return Err(anyhow!("line {}: expected 2 or 3 coordinates", idx + 1)),
+        };
+        stations.entry(station).or_default().push(point);
+    }
+    Ok(stations)
+}
+
+// Antinodes of antennas at arbitrary positions, which unlike the grid are
+// not bounded, so harmonics need a maximum range.
+pub fn point_cloud(pointsfile: &str, rules: &Resonance) -> Result<()> {
+    let stations = parse_points(&fs::read_to_string(pointsfile)?)?;
+    let antinodes = resonate(&stations, None, rules)?;
+    let mut keys: Vec<&char> = antinodes.keys().collect();
+    keys.sort();
+    for station in keys {
+        let mut anti: Vec<&Point> = antinodes[station].iter().collect();
+        anti.sort();
+        println!("{station}: {} antinodes {:?}", anti.len(), anti);
+    }
+    let distinct: HashSet<&Point> = antinodes.values().flatten().collect();
+    println!("{} distinct positions", distinct.len());
+    Ok(())
 }
 
 #[test]
@@ -126,6 +440,87 @@ fn test_part2() {
     assert_eq!(part2("./input/day8_test.txt").unwrap(), 34);
 }
 
+#[test]
+fn test_exact_antinodes() {
+    // far more than 100 steps along a single row
+    let mut row = ".".repeat(150);
+    row.replace_range(0..2, "00");
+    let grid = parse_grid(&row);
+    let stations = find_stations(&grid);
+    let antinodes = find_antinodes(&grid, &stations, &Resonance::harmonics(false));
+    assert_eq!(unique_positions(&antinodes), 150);
+    // only every other point on the diagonal is a multiple of (2, 2)
+    let data = "a....\n.....\n..a..\n.....\n.....\n";
+    let grid = parse_grid(data);
+    let stations = find_stations(&grid);
+    for (rules, expected) in [
+        (Resonance::default(), 1),
+        (Resonance::harmonics(false), 3),
+        (Resonance::harmonics(true), 5),
+    ] {
+        let antinodes = find_antinodes(&grid, &stations, &rules);
+        assert_eq!(unique_positions(&antinodes), expected);
+    }
+}
+
+#[test]
+fn test_breakdown() {
+    let grid = read_input("./input/day8_test.txt").unwrap();
+    let stations = find_stations(&grid);
+    let antinodes = find_antinodes(&grid, &stations, &Resonance::default());
+    assert_eq!(grid, read_input("./input/day8_test.txt").unwrap());
+    assert_eq!(antinodes[&'0'].len(), 10);
+    assert_eq!(antinodes[&'A'].len(), 5);
+    assert_eq!(shared_counts(&antinodes), BTreeMap::from([(2, 1)]));
+    let out = render(&grid, &antinodes);
+    // the antenna at row 5 col 6 is also an antinode of '0'
+    assert!(out.contains(&format!("{}A{RESET}", colour(&antinodes)[&'0'])));
+    assert!(out.contains(&format!("{SHARED}#{RESET}")));
+}
+
+#[test]
+fn test_resonance() {
+    let options = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let rules = Resonance::from_options(&options("--ratio 3:1 --range 2")).unwrap();
+    assert_eq!(rules.rule, Rule::Ratio(3, 1));
+    assert_eq!(rules.max_range, Some(2));
+    assert!(Resonance::from_options(&options("--ratio 1:2")).is_err());
+    let err = |s: &str| {
+        Resonance::from_options(&options(s))
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(err("--range --overlay"), "--range needs a value");
+    assert_eq!(err("--rnage 3"), "unknown option --rnage");
+    assert_eq!(err("--ratio 3:x"), "--ratio needs whole numbers, not \"x\"");
+    assert!(Resonance::from_options(&options("--overlay --points p.txt --range 3")).is_ok());
+    let stations = parse_points("a 0,0,0\na 2 4 6 # comment\n\nb 1,1\n").unwrap();
+    assert_eq!(stations[&'b'], vec![[1, 1, 0]]);
+    // 3 times as far from one antenna as the other, i.e. half the separation beyond
+    let rules = Resonance::from_options(&options("--ratio 3:1")).unwrap();
+    let antinodes = resonate(&stations, None, &rules).unwrap();
+    assert_eq!(antinodes[&'a'], HashSet::from([[-1, -2, -3], [3, 6, 9]]));
+    assert!(antinodes[&'b'].is_empty());
+    let rules = Resonance {
+        rule: Rule::Harmonics { reduce: true },
+        max_range: Some(4),
+    };
+    let antinodes = resonate(&stations, None, &rules).unwrap();
+    assert_eq!(
+        antinodes[&'a'],
+        HashSet::from([[-1, -2, -3], [0, 0, 0], [1, 2, 3], [2, 4, 6], [3, 6, 9]])
+    );
+    assert!(resonate(&stations, None, &Resonance::harmonics(false)).is_err());
+    // antennas whose separation or antinodes don't fit in an i32
+    let far = parse_points("a 2000000000,0\na -2000000000,0\n").unwrap();
+    let rules = Resonance::from_options(&options("--ratio 3:1")).unwrap();
+    assert!(resonate(&far, None, &rules).is_err());
+    let far = parse_points("a 1000000000,0\na 2000000000,0\n").unwrap();
+    assert!(resonate(&far, None, &Resonance::default()).is_err());
+    assert!(parse_points("ab 1,2").is_err());
+    assert!(parse_points("a 1").is_err());
+}
+
 pub fn run(inputfile: &str) -> Result<(i32, i32)> {
     Ok((part1(inputfile)?, part2(inputfile)?))
 }
@@ -1,30 +1,58 @@
-use anyhow::Result;
+#[cfg(test)]
+use crate::testing::Lcg;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::iter;
+use std::ops::Range;
 
 enum Disk {
     File((usize, usize)),
     Empty(usize),
 }
 
+// A line of dense digits, alternately file and free lengths, optionally
+// followed by a line of comma separated IDs for the files when they are not
+// simply numbered in order, as written by `save_str`. An ID given more than
+// once is a file in several pieces.
+fn read_input_str(data: &str) -> Result<Vec<Disk>> {
+    let mut lines = data.lines();
+    let digits = lines.next().unwrap_or("").trim_end();
+    let ids = match lines.next() {
+        Some(line) if !line.trim().is_empty() => Some(
+            line.split(',')
+                .map(|field| field.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        _ => None,
+    };
+    let n_files = digits.len().div_ceil(2);
+    if let Some(ids) = &ids {
+        if ids.len() != n_files {
+            return Err(anyhow!("expected {n_files} file IDs"));
+        }
+    }
+    let mut file_map = vec![];
+    for (idx, ch) in digits.chars().enumerate() {
+        let length =
+            ch.to_digit(10)
+                .ok_or_else(|| anyhow!("bad digit {ch:?} at position {idx}"))? as usize;
+        if idx % 2 == 0 {
+            let id = ids.as_ref().map_or(idx / 2, |ids| ids[idx / 2]);
+            file_map.push(Disk::File((id, length)));
+        } else {
+            file_map.push(Disk::Empty(length));
+        }
+    }
+    Ok(file_map)
+}
+
 fn read_input(inputfile: &str) -> Result<Vec<Disk>> {
     let data = fs::read_to_string(inputfile)?;
-    let file_map: Vec<Disk> = data
-        .chars()
-        .map(|ch| ch.to_digit(10).unwrap() as usize)
-        .enumerate()
-        .map(|(idx, length)| {
-            if idx % 2 == 0 {
-                Disk::File((idx / 2, length))
-            } else {
-                Disk::Empty(length)
-            }
-        })
-        .collect();
-    Ok(file_map)
+    read_input_str(&data)
 }
 
-fn expand_blocks(file_map: &Vec<Disk>) -> Vec<Option<usize>> {
+fn expand_blocks(file_map: &[Disk]) -> Vec<Option<usize>> {
     file_map
         .iter()
         .flat_map(|disk| match disk {
@@ -36,12 +64,13 @@ fn expand_blocks(file_map: &Vec<Disk>) -> Vec<Option<usize>> {
 
 fn compress(full_map: &mut Vec<Option<usize>>) {
     let mut first_free = 0;
-    let mut last_file = full_map.len() - 1;
+    let mut last_file = full_map.len().saturating_sub(1);
     loop {
-        while let Some(_) = full_map[first_free] {
+        // a saved layout may have no free space left, or no files
+        while first_free < full_map.len() && full_map[first_free].is_some() {
             first_free += 1;
         }
-        while let None = full_map[last_file] {
+        while last_file > 0 && full_map[last_file].is_none() {
             last_file -= 1;
         }
         if first_free >= last_file {
@@ -52,10 +81,458 @@ fn compress(full_map: &mut Vec<Option<usize>>) {
     }
 }
 
-fn compress_files(file_map: &Vec<Disk>, block_map: &mut Vec<Option<usize>>) {
-    for file in file_map.iter().rev() {
+// A file of `len` blocks starting at block `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileSpan {
+    id: usize,
+    start: usize,
+    len: usize,
+}
+
+// The files in the order they appear on the disk, and the free extents as
+// (start, length).
+fn spans(file_map: &[Disk]) -> (Vec<FileSpan>, Vec<(usize, usize)>) {
+    let mut files = vec![];
+    let mut free = vec![];
+    let mut start = 0;
+    for disk in file_map {
+        match *disk {
+            Disk::File((id, len)) => {
+                files.push(FileSpan { id, start, len });
+                start += len;
+            }
+            Disk::Empty(len) => {
+                free.push((start, len));
+                start += len;
+            }
+        }
+    }
+    (files, free)
+}
+
+// Which free extent a whole file moves into, out of those before it that
+// are big enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fit {
+    // the leftmost, as in the puzzle
+    First,
+    // the smallest, leaving the least space behind
+    Best,
+    // the largest
+    Worst,
+    // the leftmost after where the last file was put, wrapping around
+    Next,
+}
+
+// Free extents, as start positions in an ordered set for each length and
+// the length of the extent at each start, so that space left behind by a
+// file can be merged with the extents either side of it.
+#[derive(Default)]
+struct FreeSpace {
+    by_len: BTreeMap<usize, BTreeSet<usize>>,
+    by_start: BTreeMap<usize, usize>,
+}
+
+impl FreeSpace {
+    fn insert(&mut self, start: usize, len: usize) {
+        if len > 0 {
+            self.by_len.entry(len).or_default().insert(start);
+            self.by_start.insert(start, len);
+        }
+    }
+
+    fn remove(&mut self, start: usize, len: usize) {
+        let starts = self.by_len.get_mut(&len).unwrap();
+        starts.remove(&start);
+        if starts.is_empty() {
+            self.by_len.remove(&len);
+        }
+        self.by_start.remove(&start);
+    }
+
+    // Free the blocks a file has moved out of.
+    fn release(&mut self, mut start: usize, mut len: usize) {
+        if let Some((&before, &before_len)) = self.by_start.range(..start).next_back() {
+            if before + before_len == start {
+                self.remove(before, before_len);
+                start = before;
+                len += before_len;
+            }
+        }
+        if let Some(&after_len) = self.by_start.get(&(start + len)) {
+            self.remove(start + len, after_len);
+            len += after_len;
+        }
+        self.insert(start, len);
+    }
+
+    // The leftmost extent starting in `range` for each length of at least
+    // `min_len`, shortest first.
+    fn leftmost(
+        &self,
+        range: Range<usize>,
+        min_len: usize,
+    ) -> impl DoubleEndedIterator<Item = (usize, usize)> + '_ {
+        self.by_len
+            .range(min_len..)
+            .filter_map(move |(&len, starts)| {
+                starts
+                    .range(range.clone())
+                    .next()
+                    .map(|&start| (start, len))
+            })
+    }
+}
+
+// Move each whole file, highest ID first, into a free extent before it
+// chosen by `fit`. Only the leftmost extent of each length needs looking
+// at, so each file only looks at one or two entries of each set in
+// `FreeSpace`.
+fn compress_files(file_map: &[Disk], fit: Fit) -> Vec<FileSpan> {
+    let (mut files, free) = spans(file_map);
+    let mut space = FreeSpace::default();
+    for (start, len) in free {
+        space.insert(start, len);
+    }
+    let mut cursor = 0;
+    // a saved layout lists files by position rather than ID
+    files.sort_by_key(|file| file.id);
+    // the furthest right of the files still to move after each one; space
+    // left behind past that can never be used, as when files are in order
+    let reach: Vec<usize> = files
+        .iter()
+        .scan(0, |furthest, file| {
+            let before = *furthest;
+            *furthest = before.max(file.start);
+            Some(before)
+        })
+        .collect();
+    for (file, reach) in files.iter_mut().zip(reach).rev() {
+        let before = 0..file.start;
+        let chosen = match fit {
+            Fit::First => space.leftmost(before, file.len).min(),
+            Fit::Best => space.leftmost(before, file.len).next(),
+            Fit::Worst => space.leftmost(before, file.len).next_back(),
+            Fit::Next => {
+                let after_cursor = cursor.min(file.start)..file.start;
+                let wrapped = 0..cursor.min(file.start);
+                space
+                    .leftmost(after_cursor, file.len)
+                    .min()
+                    .or_else(|| space.leftmost(wrapped, file.len).min())
+            }
+        };
+        if let Some((start, len)) = chosen {
+            space.remove(start, len);
+            space.insert(start + file.len, len - file.len);
+            if file.start < reach {
+                space.release(file.start, file.len);
+            }
+            file.start = start;
+            cursor = start + file.len;
+        }
+    }
+    files
+}
+
+// Lay the files out as a block map `len` blocks long.
+fn blocks_from_files(files: &[FileSpan], len: usize) -> Vec<Option<usize>> {
+    let mut block_map = vec![None; len];
+    for f in files {
+        block_map[f.start..f.start + f.len].fill(Some(f.id));
+    }
+    block_map
+}
+
+fn checksum_files(files: &[FileSpan]) -> usize {
+    files
+        .iter()
+        .map(|f| f.id * (f.start * f.len + f.len * f.len.saturating_sub(1) / 2))
+        .sum()
+}
+
+fn checksum(full_map: &Vec<Option<usize>>) -> usize {
+    let mut sum = 0;
+    for (pos, file_id) in full_map.iter().enumerate() {
+        if file_id.is_some() {
+            sum += pos * file_id.unwrap();
+        }
+    }
+    sum
+}
+
+// Runs of identical blocks as (block, length).
+fn runs(full_map: &[Option<usize>]) -> Vec<(Option<usize>, usize)> {
+    let mut runs: Vec<(Option<usize>, usize)> = vec![];
+    for &block in full_map {
+        match runs.last_mut() {
+            Some((last, len)) if *last == block => *len += 1,
+            _ => runs.push((block, 1)),
+        }
+    }
+    runs
+}
+
+// The dense digits for a block map, along with the ID of each file in
+// them. A file in several pieces, as after compacting block by block, has
+// its ID once for each piece, and so does a file longer than 9 blocks,
+// split into pieces of up to 9. Free runs longer than 9 are split by
+// zero-length files, and files next to each other are separated by an
+// empty free extent. The zero-length files get IDs above every real one so
+// they can't be mistaken for part of a file.
+fn encode(full_map: &[Option<usize>]) -> (String, Vec<usize>) {
+    let mut digits = String::new();
+    let mut ids = vec![];
+    let mut next_filler = full_map.iter().flatten().max().map_or(0, |id| id + 1);
+    for (block, mut len) in runs(full_map) {
+        match block {
+            Some(id) => {
+                while len > 0 {
+                    if !digits.len().is_multiple_of(2) {
+                        digits.push('0');
+                    }
+                    let piece = len.min(9);
+                    ids.push(id);
+                    digits.push(char::from_digit(piece as u32, 10).unwrap());
+                    len -= piece;
+                }
+            }
+            None => {
+                if digits.len().is_multiple_of(2) {
+                    digits.push('0');
+                    ids.push(next_filler);
+                    next_filler += 1;
+                }
+                while len > 0 {
+                    let piece = len.min(9);
+                    digits.push(char::from_digit(piece as u32, 10).unwrap());
+                    len -= piece;
+                    if len > 0 {
+                        digits.push('0');
+                        ids.push(next_filler);
+                        next_filler += 1;
+                    }
+                }
+            }
+        }
+    }
+    (digits, ids)
+}
+
+// The block map in the puzzle's format, with the line of file IDs only
+// when they are not 0, 1, 2, ...
+fn save_str(full_map: &[Option<usize>]) -> String {
+    let (digits, ids) = encode(full_map);
+    if ids.iter().enumerate().all(|(idx, &id)| idx == id) {
+        format!("{digits}\n")
+    } else {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        format!("{digits}\n{}\n", ids.join(","))
+    }
+}
+
+// Check that saving and reading back the block map gives the same blocks.
+fn round_trip(full_map: &[Option<usize>]) -> Result<()> {
+    let reloaded = expand_blocks(&read_input_str(&save_str(full_map))?);
+    let len = full_map.len().max(reloaded.len());
+    if let Some(pos) = (0..len).find(|&pos| full_map.get(pos) != reloaded.get(pos)) {
+        return Err(anyhow!(
+            "block {pos} was {:?} but reads back as {:?}",
+            full_map.get(pos),
+            reloaded.get(pos)
+        ));
+    }
+    Ok(())
+}
+
+// Draw the blocks with '.' for free space. When some IDs have more than
+// one digit every block is padded to the same width and separated by a
+// space.
+fn format_map(full_map: &[Option<usize>]) -> String {
+    let width = full_map
+        .iter()
+        .flatten()
+        .map(|id| id.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let cells = full_map.iter().map(|block| match block {
+        Some(id) => format!("{id:>width$}"),
+        None => format!("{:>width$}", "."),
+    });
+    if width == 1 {
+        cells.collect()
+    } else {
+        cells.collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn _print_map(full_map: &[Option<usize>]) {
+    println!("{}", format_map(full_map));
+}
+
+// How to compact the disk: block by block as in part 1, or whole files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    Blocks,
+    Files(Fit),
+}
+
+// Look up a strategy by name.
+fn strategy(name: &str) -> Option<Strategy> {
+    match name {
+        "blocks" => Some(Strategy::Blocks),
+        "first-fit" => Some(Strategy::Files(Fit::First)),
+        "best-fit" => Some(Strategy::Files(Fit::Best)),
+        "worst-fit" => Some(Strategy::Files(Fit::Worst)),
+        "next-fit" => Some(Strategy::Files(Fit::Next)),
+        _ => None,
+    }
+}
+
+const STRATEGIES: [&str; 5] = ["blocks", "first-fit", "best-fit", "worst-fit", "next-fit"];
+
+// The block map after compacting with `strategy`.
+fn compact(file_map: &[Disk], strategy: Strategy) -> Vec<Option<usize>> {
+    let mut block_map = expand_blocks(file_map);
+    match strategy {
+        Strategy::Blocks => compress(&mut block_map),
+        Strategy::Files(fit) => {
+            block_map = blocks_from_files(&compress_files(file_map, fit), block_map.len())
+        }
+    }
+    block_map
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Metrics {
+    // runs of free blocks before the last file block
+    free_extents: usize,
+    largest_free: usize,
+    // files with every block where it started
+    unmoved: usize,
+    // blocks now holding a different file than before, including space
+    // vacated by another file during compaction
+    moves: usize,
+}
+
+fn metrics(before: &[Option<usize>], after: &[Option<usize>]) -> Metrics {
+    let mut metrics = Metrics::default();
+    let end = after
+        .iter()
+        .rposition(|b| b.is_some())
+        .map_or(0, |pos| pos + 1);
+    let mut run = 0;
+    for block in &after[..end] {
+        if block.is_none() {
+            run += 1;
+            continue;
+        }
+        if run > 0 {
+            metrics.free_extents += 1;
+            metrics.largest_free = metrics.largest_free.max(run);
+            run = 0;
+        }
+    }
+    let mut moved = HashMap::new();
+    for (b, a) in before.iter().zip(after) {
+        if a.is_some() && a != b {
+            metrics.moves += 1;
+        }
+        if let Some(id) = b {
+            *moved.entry(id).or_insert(false) |= a != b;
+        }
+    }
+    metrics.unmoved = moved.values().filter(|&&m| !m).count();
+    metrics
+}
+
+// Compact the disk with each of the named strategies, or all of them,
+// printing the checksum and how fragmented the free space is afterwards,
+// and with `show` the blocks themselves.
+pub fn defrag(inputfile: &str, names: &[&str], show: bool) -> Result<()> {
+    let file_map = read_input(inputfile)?;
+    let before = expand_blocks(&file_map);
+    let names = names.iter().flat_map(|name| {
+        if *name == "all" {
+            &STRATEGIES[..]
+        } else {
+            std::slice::from_ref(name)
+        }
+    });
+    for name in names {
+        let strategy = strategy(name).ok_or_else(|| anyhow!("unknown strategy {name}"))?;
+        let after = compact(&file_map, strategy);
+        let m = metrics(&before, &after);
+        println!(
+            "{name}: checksum {} free extents {} largest free {} unmoved files {} block moves {}",
+            checksum(&after),
+            m.free_extents,
+            m.largest_free,
+            m.unmoved,
+            m.moves
+        );
+        if show {
+            println!("{}", format_map(&after));
+        }
+    }
+    Ok(())
+}
+
+// Compact the disk with the named strategy and save the result in the
+// same format as the input, so it can be read back in.
+pub fn save(inputfile: &str, name: &str, outfile: &str) -> Result<()> {
+    let file_map = read_input(inputfile)?;
+    let strategy = strategy(name).ok_or_else(|| anyhow!("unknown strategy {name}"))?;
+    let after = compact(&file_map, strategy);
+    round_trip(&after)?;
+    fs::write(outfile, save_str(&after))?;
+    println!(
+        "saved {name} layout with checksum {} to {outfile}",
+        checksum(&after)
+    );
+    Ok(())
+}
+
+fn part1(inputfile: &str) -> Result<usize> {
+    let file_map = read_input(inputfile)?;
+    let mut block_map = expand_blocks(&file_map);
+    // _print_map(&block_map);
+    compress(&mut block_map);
+    // _print_map(&block_map);
+    Ok(checksum(&block_map))
+}
+
+fn part2(inputfile: &str) -> Result<usize> {
+    let file_map = read_input(inputfile)?;
+    let files = compress_files(&file_map, Fit::First);
+    Ok(checksum_files(&files))
+}
+
+#[test]
+fn day9_test_part1() {
+    assert_eq!(part1("./input/day9_test.txt").unwrap(), 1928);
+}
+
+#[test]
+fn day9_test_part2() {
+    assert_eq!(part2("./input/day9_test.txt").unwrap(), 2858);
+}
+
+// The original block-by-block search for free space, kept to check
+// `compress_files` against.
+#[cfg(test)]
+fn compress_file_blocks(file_map: &[Disk], block_map: &mut [Option<usize>]) {
+    let mut by_id: Vec<_> = file_map.iter().collect();
+    by_id.sort_by_key(|disk| match disk {
+        Disk::File((id, _)) => *id,
+        Disk::Empty(_) => 0,
+    });
+    for file in by_id.into_iter().rev() {
         if let Disk::File((id, length)) = file {
             let length = *length;
+            if length == 0 {
+                continue;
+            }
             let file_start = block_map
                 .iter()
                 .position(|block| {
@@ -92,52 +569,168 @@ fn compress_files(file_map: &Vec<Disk>, block_map: &mut Vec<Option<usize>>) {
     }
 }
 
-fn checksum(full_map: &Vec<Option<usize>>) -> usize {
-    let mut sum = 0;
-    for (pos, file_id) in full_map.iter().enumerate() {
-        if file_id.is_some() {
-            sum += pos * file_id.unwrap();
-        }
-    }
-    sum
+// A dense disk map of `n` random digits, with no empty files as in the
+// puzzle input.
+#[cfg(test)]
+fn generate(n: usize) -> String {
+    let mut lcg = Lcg::new();
+    (0..n)
+        .map(|idx| {
+            let digit = lcg.below(10);
+            let digit = if idx % 2 == 0 { digit.max(1) } else { digit };
+            char::from(b'0' + digit as u8)
+        })
+        .collect()
 }
 
-fn _print_map(full_map: &Vec<Option<usize>>) {
-    for entry in full_map {
-        match entry {
-            Some(id) => print!("{}", id),
-            None => print!("."),
-        }
+#[test]
+fn test_compress_files() {
+    let map = "2333133121414131402";
+    let file_map = read_input_str(map).unwrap();
+    let files = compress_files(&file_map, Fit::First);
+    let block_map = blocks_from_files(&files, expand_blocks(&file_map).len());
+    let expected = "00992111777.44.333....5555.6666.....8888..";
+    assert_eq!(format_map(&block_map), expected);
+    for n in [10, 101, 2000] {
+        let file_map = read_input_str(&generate(n)).unwrap();
+        let mut block_map = expand_blocks(&file_map);
+        compress_file_blocks(&file_map, &mut block_map);
+        assert_eq!(
+            checksum_files(&compress_files(&file_map, Fit::First)),
+            checksum(&block_map)
+        );
     }
-    print!("\n");
 }
 
-fn part1(inputfile: &str) -> Result<usize> {
-    let file_map = read_input(inputfile)?;
-    let mut block_map = expand_blocks(&file_map);
-    // _print_map(&block_map);
-    compress(&mut block_map);
-    // _print_map(&block_map);
-    Ok(checksum(&block_map))
+#[test]
+fn test_strategies() {
+    let shown = |map: &str, strategy| format_map(&compact(&read_input_str(map).unwrap(), strategy));
+    let first = Strategy::Files(Fit::First);
+    assert_eq!(shown("1412101", first), "0321......");
+    assert_eq!(shown("1412101", Strategy::Files(Fit::Best)), "01....32..");
+    assert_eq!(shown("11131", first), "021....");
+    assert_eq!(shown("11131", Strategy::Files(Fit::Worst)), "01.2...");
+    assert_eq!(shown("111310102", first), "031442....");
+    assert_eq!(shown("111310102", Strategy::Files(Fit::Next)), "021443....");
+    assert_eq!(shown("111310102", Strategy::Blocks), "041432....");
+
+    let file_map = read_input("./input/day9_test.txt").unwrap();
+    let before = expand_blocks(&file_map);
+    let m = metrics(&before, &compact(&file_map, Strategy::Files(Fit::First)));
+    assert_eq!(
+        m,
+        Metrics {
+            free_extents: 5,
+            largest_free: 5,
+            unmoved: 6,
+            moves: 8
+        }
+    );
+    let m = metrics(&before, &compact(&file_map, Strategy::Blocks));
+    assert_eq!(
+        m,
+        Metrics {
+            free_extents: 0,
+            largest_free: 0,
+            unmoved: 6,
+            moves: 12
+        }
+    );
+    // file 2 moves into the first gap and file 0 into the space it left
+    let file_map = read_input_str("11111\n1,2,0\n").unwrap();
+    let before = expand_blocks(&file_map);
+    let after = compact(&file_map, first);
+    assert_eq!(format_map(&after), "120..");
+    assert_eq!(metrics(&before, &after).moves, 2);
+    for name in STRATEGIES {
+        assert!(strategy(name).is_some());
+    }
 }
 
-fn part2(inputfile: &str) -> Result<usize> {
-    let file_map = read_input(inputfile)?;
-    let mut block_map = expand_blocks(&file_map);
-    // _print_map(&block_map);
-    compress_files(&file_map, &mut block_map);
-    // _print_map(&block_map);
-    Ok(checksum(&block_map))
+#[test]
+fn test_encode() {
+    let file_map = read_input("./input/day9_test.txt").unwrap();
+    let block_map = expand_blocks(&file_map);
+    assert_eq!(save_str(&block_map), "2333133121414131402\n");
+    round_trip(&block_map).unwrap();
+    for name in STRATEGIES {
+        let after = compact(&file_map, strategy(name).unwrap());
+        round_trip(&after).unwrap();
+        let reloaded = expand_blocks(&read_input_str(&save_str(&after)).unwrap());
+        assert_eq!(checksum(&reloaded), checksum(&after));
+    }
+    // compacting block by block splits files up
+    let after = compact(&read_input_str("111310102").unwrap(), Strategy::Blocks);
+    assert_eq!(format_map(&after), "041432....");
+    assert_eq!(save_str(&after), "101010101014\n0,4,1,4,3,2\n");
+    // long free runs, and files next to each other
+    let mut block_map = vec![Some(12); 9];
+    block_map.extend([None; 10]);
+    block_map.extend([Some(3), Some(0)]);
+    let (digits, ids) = encode(&block_map);
+    assert_eq!(digits, "9901101");
+    assert_eq!(ids, vec![12, 13, 3, 0]);
+    round_trip(&block_map).unwrap();
+    block_map.extend([Some(12)]);
+    assert_eq!(
+        encode(&block_map),
+        ("9901101".to_string() + "01", vec![12, 13, 3, 0, 12])
+    );
+    round_trip(&block_map).unwrap();
+    assert_eq!(encode(&[Some(1); 10]), ("901".to_string(), vec![1, 1]));
+    round_trip(&[Some(1); 10]).unwrap();
+    let shown = format_map(&block_map[7..11]);
+    assert_eq!(shown, "12 12  .  .");
+    // nothing left to compact
+    let mut full = vec![Some(0), Some(1)];
+    compress(&mut full);
+    assert_eq!(full, vec![Some(0), Some(1)]);
+    assert!(read_input_str("12a").is_err());
+    assert!(read_input_str("123\n4").is_err());
+    let pieces = read_input_str("123\n4,4").unwrap();
+    assert_eq!(
+        expand_blocks(&pieces),
+        [Some(4), None, None, Some(4), Some(4), Some(4)]
+    );
 }
 
 #[test]
-fn day9_test_part1() {
-    assert_eq!(part1("./input/day9_test.txt").unwrap(), 1928);
+fn test_reload() {
+    // by ID file 2 goes first and takes the gap that file 0 would if files
+    // were taken by position
+    let file_map = read_input_str("11111\n1,2,0\n").unwrap();
+    let after = compact(&file_map, Strategy::Files(Fit::First));
+    assert_eq!(format_map(&after), "120..");
+    // pieces of the same file move on their own
+    let file_map = read_input_str("1110101\n0,4,1,4\n").unwrap();
+    assert_eq!(format_map(&expand_blocks(&file_map)), "0.414");
+    let after = compact(&file_map, Strategy::Files(Fit::First));
+    assert_eq!(format_map(&after), "0441.");
+    for n in [11, 101, 2001] {
+        let file_map = read_input_str(&generate(n)).unwrap();
+        let after = compact(&file_map, Strategy::Files(Fit::Worst));
+        let reloaded = read_input_str(&save_str(&after)).unwrap();
+        let mut block_map = expand_blocks(&reloaded);
+        compress_file_blocks(&reloaded, &mut block_map);
+        assert_eq!(compact(&reloaded, Strategy::Files(Fit::First)), block_map);
+    }
 }
 
+// Time whole-file compaction of a million entry disk map with each fit.
 #[test]
-fn day9_test_part2() {
-    assert_eq!(part2("./input/day9_test.txt").unwrap(), 2858);
+#[ignore]
+fn bench_compress_files() {
+    use std::time::Instant;
+
+    let file_map = read_input_str(&generate(1_000_000)).unwrap();
+    let start = Instant::now();
+    let sum = checksum_files(&compress_files(&file_map, Fit::First));
+    println!("1000000 entries: checksum {sum} in {:?}", start.elapsed());
+    for fit in [Fit::Best, Fit::Worst, Fit::Next] {
+        let start = Instant::now();
+        let sum = checksum_files(&compress_files(&file_map, fit));
+        println!("{fit:?} fit: checksum {sum} in {:?}", start.elapsed());
+    }
 }
 
 pub fn run(inputfile: &str) -> Result<(usize, usize)> {
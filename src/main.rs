@@ -16,34 +16,168 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
+#[cfg(test)]
+mod testing;
+
+// The value of a result, or exit with its error, for mistakes in the
+// options rather than bugs.
+fn or_exit<T>(result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    })
+}
+
+// The value after `flag`, if it was given, failing when it is the last
+// option or is followed by another flag.
+fn value_of<'a>(options: &'a [String], flag: &str) -> anyhow::Result<Option<&'a str>> {
+    let Some(pos) = options.iter().position(|o| o == flag) else {
+        return Ok(None);
+    };
+    match options.get(pos + 1) {
+        Some(value) if !value.starts_with("--") => Ok(Some(value)),
+        _ => Err(anyhow::anyhow!("{flag} needs a value")),
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} DAY", args[0]);
+    if args.len() < 2 {
+        eprintln!("Usage: {} DAY [OPTIONS]", args[0]);
         std::process::exit(1);
     }
     let day: u32 = args[1].parse().expect("Cannot parse day number");
+    let options = &args[2..];
     let result = match day {
         1 => day1::run("input/day1.txt").unwrap(),
         2 => day2::run("input/day2.txt").unwrap(),
-        3 => day3::run("input/day3.txt").unwrap(),
-        4 => day4::run("input/day4.txt").unwrap(),
-        5 => day5::run("input/day5.txt").unwrap(),
-        6 => day6::run("input/day6.txt").unwrap(),
+        3 => {
+            if options.iter().any(|o| o == "--annotate") {
+                day3::annotate("input/day3.txt").unwrap();
+            }
+            let result = day3::run("input/day3.txt").unwrap();
+            println!("64 bit result {:?}", result);
+            (result.0 as i32, result.1 as i32)
+        }
+        4 => {
+            if let Some(words) = or_exit(value_of(options, "--find")) {
+                let words: Vec<&str> = words.split(',').collect();
+                let wrap = options.iter().any(|o| o == "--wrap");
+                day4::search("input/day4.txt", &words, wrap).unwrap();
+            }
+            if let Some(stencilfile) = or_exit(value_of(options, "--stencil")) {
+                day4::search_stencil("input/day4.txt", stencilfile).unwrap();
+            }
+            day4::run("input/day4.txt").unwrap()
+        }
+        5 => {
+            if options.iter().any(|o| o == "--explain") {
+                day5::explain("input/day5.txt").unwrap();
+            }
+            day5::run("input/day5.txt").unwrap()
+        }
+        6 => {
+            let rules = or_exit(day6::GuardRules::from_options(options));
+            if let Some(pos) = options.iter().position(|o| o == "--loops") {
+                // the cell to draw is optional, but must make sense if given
+                let draw = options.get(pos + 1).filter(|rc| !rc.starts_with("--")).map(|rc| {
+                    let (r, c) = rc.split_once(',').unwrap_or((rc, ""));
+                    match (r.parse(), c.parse()) {
+                        (Ok(r), Ok(c)) => Ok((r, c)),
+                        _ => Err(anyhow::anyhow!("--loops needs ROW,COL, not {rc}")),
+                    }
+                });
+                let draw = or_exit(draw.transpose());
+                day6::list_loops("input/day6.txt", &rules, draw).unwrap();
+            }
+            if options.iter().any(|o| o == "--patrol") {
+                day6::explore("input/day6.txt", &rules).unwrap();
+            }
+            day6::run("input/day6.txt").unwrap()
+        }
         7 => {
+            let ops = or_exit(value_of(options, "--ops"));
+            let names: Vec<&str> = match ops {
+                Some(ops) => ops.split(',').collect(),
+                None => vec!["+", "*", "||"],
+            };
+            let precedence = options.iter().any(|o| o == "--precedence");
+            let bigint = options.iter().any(|o| o == "--bigint");
+            let all = options.iter().any(|o| o == "--all");
+            let audit = all || options.iter().any(|o| o == "--audit");
+            if audit || precedence || bigint || ops.is_some() {
+                or_exit(day7::calibrate(
+                    "input/day7.txt",
+                    &names,
+                    precedence,
+                    bigint,
+                    audit.then_some(all),
+                ));
+            }
+            if bigint {
+                // the 64 bit parts can't read numbers this big
+                return;
+            }
             let result = day7::run("input/day7.txt").unwrap();
             println!("64 bit result {:?}", result);
             (result.0 as i32, result.1 as i32)
         }
-        8 => day8::run("input/day8.txt").unwrap(),
+        8 => {
+            if options.iter().any(|o| o == "--collinear") {
+                let count = day8::collinear("input/day8.txt").unwrap();
+                println!("Collinear antinodes: {count}");
+            }
+            let rules = or_exit(day8::Resonance::from_options(options));
+            if options.iter().any(|o| o == "--overlay") {
+                day8::breakdown("input/day8.txt", &rules).unwrap();
+            }
+            if let Some(pointfile) = or_exit(value_of(options, "--points")) {
+                day8::point_cloud(pointfile, &rules).unwrap();
+            }
+            day8::run("input/day8.txt").unwrap()
+        }
         9 => {
+            let strategy = or_exit(value_of(options, "--strategy"));
+            if let Some(strategy) = strategy {
+                let names: Vec<&str> = strategy.split(',').collect();
+                let show = options.iter().any(|o| o == "--show");
+                or_exit(day9::defrag("input/day9.txt", &names, show));
+            }
+            if let Some(outputfile) = or_exit(value_of(options, "--save")) {
+                let name = strategy
+                    .map(|s| s.split(',').next().unwrap())
+                    .filter(|&name| name != "all")
+                    .unwrap_or("first-fit");
+                or_exit(day9::save("input/day9.txt", name, outputfile));
+            }
             let result = day9::run("input/day9.txt").unwrap();
             println!("64 bit result {:?}", result);
             (result.0 as i32, result.1 as i32)
         }
-        10 => day10::run("input/day10.txt").unwrap(),
-        11 => day11::run("input/day11.txt").unwrap(),
+        10 => {
+            let rules = or_exit(day10::HikingRules::from_options(options));
+            if rules != day10::HikingRules::default() {
+                day10::hike("input/day10.txt", &rules).unwrap();
+            }
+            let dot = or_exit(value_of(options, "--dot"));
+            let graphml = or_exit(value_of(options, "--graphml"));
+            if dot.is_some() || graphml.is_some() {
+                let prune = options.iter().any(|o| o == "--prune");
+                day10::export("input/day10.txt", &rules, prune, dot, graphml).unwrap();
+            }
+            day10::run("input/day10.txt").unwrap()
+        }
+        11 => {
+            if let Some(n_blink) = or_exit(value_of(options, "--blinks")) {
+                let n_blink = or_exit(
+                    n_blink
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("--blinks needs a number, not {n_blink}")),
+                );
+                day11::blinks("input/day11.txt", n_blink).unwrap();
+            }
+            day11::run("input/day11.txt").unwrap()
+        }
         12 => day12::run("input/day12.txt").unwrap(),
         13 => {
             let result = day13::run("input/day13.txt").unwrap();
@@ -0,0 +1,24 @@
+// Helpers shared by the tests of several days.
+
+// A fixed-seed linear congruential generator, so generated inputs are the
+// same on every run.
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new() -> Lcg {
+        Lcg(2024)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0
+    }
+
+    // A value in 0..n taken from the high bits.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() >> 33) as usize % n
+    }
+}